@@ -1,6 +1,9 @@
+mod shell;
+
 use clap::Parser;
 use dotenvy::from_path_iter;
-use std::path::PathBuf;
+use shell::Shell;
+use std::path::{Path, PathBuf};
 use std::process;
 
 /// Load environment variables from a .env file into the current shell session
@@ -9,111 +12,150 @@ use std::process;
     author,
     version,
     about,
-    long_about = "Load environment variables from a .env file into the current shell session.\n\nThis tool reads environment variables from a .env file and outputs shell export \ncommands that can be evaluated to set those variables in the current shell.\n\nUsage example:\n\tload_env .env\n\tload_env /path/to/your/.env\n\nTo actually set the environment variables in your current shell, you need to \nevaluate the output of this tool:\n\tsource <(load_env .env)         # for bash/zsh\n\tload_env .env | source          # for fish",
-    after_help = "Note: To actually set the environment variables in your current shell, you need to \nevaluate the output of this tool:\n\tsource <(load_env .env)         # for bash/zsh\n\tload_env .env | source          # for fish"
+    long_about = "Load environment variables from a .env file into the current shell session.\n\nThis tool reads environment variables from a .env file and outputs the commands \nof the chosen shell that can be evaluated to set those variables in the current shell.\n\nUsage example:\n\tload_env .env\n\tload_env /path/to/your/.env\n\tload_env .env .env.local          # later files override earlier ones\n\tload_env --mode development       # .env, .env.local, .env.development, .env.development.local\n\nTo actually set the environment variables in your current shell, you need to \nevaluate the output of this tool:\n\tsource <(load_env .env)                      # for bash/zsh\n\tload_env .env | source                        # for fish\n\tload_env .env | save -f env.nu; source env.nu # for nushell\n\tload_env .env | Out-String | Invoke-Expression   # for PowerShell\n\teval (load_env .env | slurp)                  # for elvish\n\tload_env .env > env.csh; source env.csh       # for tcsh\n\nThe shell is detected from the parent process or $SHELL; use --shell to choose it.",
+    after_help = "Note: To actually set the environment variables in your current shell, you need to \nevaluate the output of this tool:\n\tsource <(load_env .env)                      # for bash/zsh\n\tload_env .env | source                        # for fish\n\tload_env .env | save -f env.nu; source env.nu # for nushell\n\tload_env .env | Out-String | Invoke-Expression   # for PowerShell\n\teval (load_env .env | slurp)                  # for elvish\n\tload_env .env > env.csh; source env.csh       # for tcsh\n\nThe shell is detected from the parent process or $SHELL; use --shell to choose it."
 )]
 struct Args {
-    /// Path to the .env file
-    #[clap(value_parser)]
-    env_file: PathBuf,
+    /// Paths to the .env files; later files override earlier ones
+    #[clap(value_parser, required_unless_present_any = ["print_detected", "mode"])]
+    env_files: Vec<PathBuf>,
+
+    /// Load .env, .env.local, .env.<MODE> and .env.<MODE>.local from the current
+    /// directory before the given files, skipping the ones that don't exist
+    #[clap(long, value_name = "MODE")]
+    mode: Option<String>,
+
+    /// The shell to write the commands for, instead of detecting it
+    #[clap(long, value_enum)]
+    shell: Option<Shell>,
+
+    /// Print the detected shell and where it was detected from, then exit
+    #[clap(long)]
+    print_detected: bool,
 }
 
 fn main() {
     let args = Args::parse();
+    let (shell, detection) = shell::detect();
+
+    if args.print_detected {
+        println!("{} ({})", shell, detection);
+        return;
+    }
+    let shell = args.shell.unwrap_or(shell);
+
+    let vars = match load_layers(Path::new("."), args.mode.as_deref(), &args.env_files) {
+        Ok(vars) => vars,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Output the commands that set each environment variable
+    // This allows the shell to evaluate the output and set the variables
+    print!("{}", shell.script(&vars));
+}
+
+/// The files `--mode <MODE>` expands to, from the lowest precedence to the highest
+fn mode_files(mode: &str) -> Vec<PathBuf> {
+    vec![
+        PathBuf::from(".env"),
+        PathBuf::from(".env.local"),
+        PathBuf::from(format!(".env.{}", mode)),
+        PathBuf::from(format!(".env.{}.local", mode)),
+    ]
+}
+
+/// Read the `--mode` cascade from `dir`, then the given files, each overriding the
+/// variables of the ones before. Missing cascade files are skipped; a missing given
+/// file is an error.
+fn load_layers(
+    dir: &Path,
+    mode: Option<&str>,
+    env_files: &[PathBuf],
+) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for file in mode.map(mode_files).unwrap_or_default() {
+        let path = dir.join(file);
+        if path.exists() {
+            load(&path, &mut vars)?;
+        }
+    }
+    for env_file in env_files {
+        load(env_file, &mut vars)?;
+    }
+    Ok(vars)
+}
 
+/// Read the variables of a .env file into `vars`, overriding those of earlier files
+fn load(env_file: &Path, vars: &mut Vec<(String, String)>) -> Result<(), String> {
     // Check if the file exists
-    if !args.env_file.exists() {
-        eprintln!(
-            "Error: The specified .env file does not exist: {:?}",
-            args.env_file
-        );
-        process::exit(1);
+    if !env_file.exists() {
+        return Err(format!(
+            "The specified .env file does not exist: {:?}",
+            env_file
+        ));
     }
 
     // Check if the path is a file
-    if !args.env_file.is_file() {
-        eprintln!(
-            "Error: The specified path is not a file: {:?}",
-            args.env_file
-        );
-        process::exit(1);
+    if !env_file.is_file() {
+        return Err(format!("The specified path is not a file: {:?}", env_file));
     }
 
     // Try to parse the .env file
-    match from_path_iter(&args.env_file) {
-        Ok(iter) => {
-            // Output export commands for each environment variable
-            // This allows the shell to evaluate the output and set the variables
-            for item in iter {
-                match item {
-                    Ok((key, value)) => {
-                        // Skip empty keys
-                        if key.is_empty() {
-                            continue;
-                        }
-                        // Properly escape the value for shell export
-                        let escaped_value = escape_for_shell(&value);
-                        println!("export {}={}", key, escaped_value);
-                    }
-                    Err(e) => {
-                        eprintln!("Error parsing .env file: {}", e);
-                        process::exit(1);
-                    }
-                }
-            }
+    let iter = from_path_iter(env_file)
+        .map_err(|e| format!("Failed to read .env file {:?}: {}", env_file, e))?;
+    for item in iter {
+        let (key, value) =
+            item.map_err(|e| format!("Failed to parse .env file {:?}: {}", env_file, e))?;
+        // Skip empty keys
+        if key.is_empty() {
+            continue;
         }
-        Err(e) => {
-            eprintln!("Error reading .env file: {}", e);
-            process::exit(1);
+        // A later file replaces the value but the variable keeps its place
+        match vars.iter_mut().find(|(name, _)| *name == key) {
+            Some(var) => var.1 = value,
+            None => vars.push((key, value)),
         }
     }
-}
-
-/// Escape a string for safe use in a shell export command
-fn escape_for_shell(value: &str) -> String {
-    // For simplicity, we'll wrap the value in single quotes
-    // and escape any single quotes within the value
-    let escaped = value.replace("'", "'\"'\"'");
-    format!("'{}'", escaped)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
-    fn test_escape_for_shell() {
-        // Test basic string without quotes
-        assert_eq!(escape_for_shell("hello"), "'hello'");
-
-        // Test string with single quotes
-        assert_eq!(escape_for_shell("hello'world"), "'hello'\"'\"'world'");
-
-        // Test string with multiple single quotes
+    fn test_load_layers() {
+        let dir = std::env::temp_dir().join(format!("load_env-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(".env"), "PORT=3000\nDEBUG=false\n").unwrap();
+        fs::write(
+            dir.join(".env.development"),
+            "DEBUG=true\nAPI_URL=http://localhost\n",
+        )
+        .unwrap();
+        fs::write(dir.join("ci.env"), "PORT=8080\n").unwrap();
+
+        // .env.local and .env.development.local don't exist and are skipped
+        let vars = load_layers(&dir, Some("development"), &[dir.join("ci.env")]).unwrap();
+        let pairs: Vec<(&str, &str)> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
         assert_eq!(
-            escape_for_shell("hello''world"),
-            "'hello'\"'\"''\"'\"'world'"
+            pairs,
+            vec![
+                ("PORT", "8080"),
+                ("DEBUG", "true"),
+                ("API_URL", "http://localhost")
+            ]
         );
 
-        // Test empty string
-        assert_eq!(escape_for_shell(""), "''");
-
-        // Test string with special characters
-        assert_eq!(escape_for_shell("hello$world"), "'hello$world'");
-    }
-
-    #[test]
-    fn test_escape_for_shell_complex() {
-        // Test string with double quotes
-        assert_eq!(escape_for_shell("hello\"world"), "'hello\"world'");
-
-        // Test string with mixed quotes
-        assert_eq!(escape_for_shell("hello'\"world"), "'hello'\"'\"'\"world'");
-
-        // Test string with spaces
-        assert_eq!(escape_for_shell("hello world"), "'hello world'");
-
-        // Test string with special shell characters
-        assert_eq!(escape_for_shell("hello $world !@#"), "'hello $world !@#'");
+        // A file given by name has to exist, even when it's also a --mode layer
+        let missing =
+            load_layers(&dir, Some("development"), &[dir.join(".env.local")]).unwrap_err();
+        assert!(missing.contains("does not exist"));
+        assert!(load_layers(&dir, Some("production"), &[]).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
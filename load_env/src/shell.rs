@@ -0,0 +1,308 @@
+use clap::ValueEnum;
+use std::env;
+use std::fmt;
+
+/// The shells `load_env` can write commands for
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+    Pwsh,
+    Elvish,
+    Tcsh,
+}
+
+/// Where the shell to write for was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detection {
+    /// The name of the process that ran `load_env`
+    ParentProcess(String),
+    /// The login shell in `$SHELL`
+    ShellVar(String),
+    /// Nothing recognizable, so POSIX syntax is used
+    Fallback,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Detection::ParentProcess(name) => write!(f, "parent process '{}'", name),
+            Detection::ShellVar(value) => write!(f, "$SHELL '{}'", value),
+            Detection::Fallback => write!(f, "fallback to POSIX syntax"),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Detect the shell that will evaluate the output: the parent process first, since
+/// that's the shell running `load_env`, then the login shell in `$SHELL`
+pub fn detect() -> (Shell, Detection) {
+    detect_from(parent_process_name(), env::var("SHELL").ok())
+}
+
+fn detect_from(parent: Option<String>, shell_var: Option<String>) -> (Shell, Detection) {
+    if let Some(name) = parent
+        && let Some(shell) = Shell::from_name(&name)
+    {
+        return (shell, Detection::ParentProcess(name));
+    }
+    if let Some(value) = shell_var
+        && let Some(shell) = Shell::from_name(&value)
+    {
+        return (shell, Detection::ShellVar(value));
+    }
+    (Shell::Bash, Detection::Fallback)
+}
+
+/// The command name of the parent process, from `/proc/<ppid>/comm` on Linux
+#[cfg(unix)]
+fn parent_process_name() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", ppid)).ok()?;
+    Some(name.trim().to_string())
+}
+
+#[cfg(not(unix))]
+fn parent_process_name() -> Option<String> {
+    None
+}
+
+impl Shell {
+    /// The shell of a program name or path such as `-zsh`, `/usr/bin/fish` or `pwsh.exe`.
+    /// Other POSIX shells get bash syntax
+    fn from_name(name: &str) -> Option<Shell> {
+        let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        let name = name.trim_start_matches('-').to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        match name {
+            "bash" | "sh" | "dash" | "ash" | "ksh" | "mksh" | "busybox" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "nu" => Some(Shell::Nu),
+            "pwsh" | "powershell" => Some(Shell::Pwsh),
+            "elvish" => Some(Shell::Elvish),
+            "tcsh" | "csh" => Some(Shell::Tcsh),
+            _ => None,
+        }
+    }
+
+    /// Build the commands that set every variable in this shell
+    pub fn script(self, vars: &[(String, String)]) -> String {
+        let lines: String = vars
+            .iter()
+            .map(|(key, value)| self.line(key, value) + "\n")
+            .collect();
+        match self {
+            // Nushell sets variables from a record, all at once
+            Shell::Nu => format!("load-env {{\n{}}}\n", lines),
+            _ => lines,
+        }
+    }
+
+    fn line(self, key: &str, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("export {}={}", key, escape_for_shell(value)),
+            Shell::Fish => format!("set -gx {} {}", key, escape_for_fish(value)),
+            Shell::Nu => format!("    {}: {}", escape_for_nu(key), escape_for_nu(value)),
+            Shell::Pwsh => format!("$env:{} = {}", key, escape_for_pwsh(value)),
+            Shell::Elvish => format!("set-env {} {}", key, escape_for_elvish(value)),
+            Shell::Tcsh => format!("setenv {} {}", key, escape_for_tcsh(value)),
+        }
+    }
+}
+
+/// Escape a string for safe use in a shell export command
+fn escape_for_shell(value: &str) -> String {
+    // For simplicity, we'll wrap the value in single quotes
+    // and escape any single quotes within the value
+    let escaped = value.replace("'", "'\"'\"'");
+    format!("'{}'", escaped)
+}
+
+/// Fish single quotes only treat `\\` and `\'` as escapes
+fn escape_for_fish(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('\'', "\\'");
+    format!("'{}'", escaped)
+}
+
+/// Nushell double quotes take C-style escapes, so control characters stay on one line
+fn escape_for_nu(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// PowerShell single quotes are escaped by doubling; typographic single quotes
+/// also delimit strings there, so they are doubled too
+fn escape_for_pwsh(value: &str) -> String {
+    let mut escaped = String::from("'");
+    for ch in value.chars() {
+        if matches!(ch, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            escaped.push(ch);
+        }
+        escaped.push(ch);
+    }
+    escaped.push('\'');
+    escaped
+}
+
+/// Elvish single quotes are escaped by doubling
+fn escape_for_elvish(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Tcsh expands `!` history references even inside single quotes, and a newline
+/// inside quotes has to be escaped with a backslash
+fn escape_for_tcsh(value: &str) -> String {
+    let mut escaped = String::from("'");
+    for ch in value.chars() {
+        match ch {
+            '\'' => escaped.push_str("'\\''"),
+            '!' => escaped.push_str("\\!"),
+            '\n' => escaped.push_str("\\\n"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped.push('\'');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_for_shell() {
+        // Test basic string without quotes
+        assert_eq!(escape_for_shell("hello"), "'hello'");
+
+        // Test string with single quotes
+        assert_eq!(escape_for_shell("hello'world"), "'hello'\"'\"'world'");
+
+        // Test string with multiple single quotes
+        assert_eq!(
+            escape_for_shell("hello''world"),
+            "'hello'\"'\"''\"'\"'world'"
+        );
+
+        // Test empty string
+        assert_eq!(escape_for_shell(""), "''");
+
+        // Test string with special characters
+        assert_eq!(escape_for_shell("hello$world"), "'hello$world'");
+    }
+
+    #[test]
+    fn test_escape_for_shell_complex() {
+        // Test string with double quotes
+        assert_eq!(escape_for_shell("hello\"world"), "'hello\"world'");
+
+        // Test string with mixed quotes
+        assert_eq!(escape_for_shell("hello'\"world"), "'hello'\"'\"'\"world'");
+
+        // Test string with spaces
+        assert_eq!(escape_for_shell("hello world"), "'hello world'");
+
+        // Test string with special shell characters
+        assert_eq!(escape_for_shell("hello $world !@#"), "'hello $world !@#'");
+    }
+
+    #[test]
+    fn test_escape_for_other_shells() {
+        let value = "it's a\\b $HOME !1\nnext";
+        assert_eq!(escape_for_fish(value), "'it\\'s a\\\\b $HOME !1\nnext'");
+        assert_eq!(escape_for_nu(value), "\"it's a\\\\b $HOME !1\\nnext\"");
+        assert_eq!(
+            escape_for_nu("say \"hi\"\u{1b}"),
+            "\"say \\\"hi\\\"\\u{1b}\""
+        );
+        assert_eq!(escape_for_pwsh(value), "'it''s a\\b $HOME !1\nnext'");
+        assert_eq!(escape_for_pwsh("it’s"), "'it’’s'");
+        assert_eq!(escape_for_elvish(value), "'it''s a\\b $HOME !1\nnext'");
+        assert_eq!(escape_for_tcsh(value), "'it'\\''s a\\b $HOME \\!1\\\nnext'");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Shell::from_name("-zsh"), Some(Shell::Zsh));
+        assert_eq!(Shell::from_name("/usr/local/bin/fish"), Some(Shell::Fish));
+        assert_eq!(
+            Shell::from_name("C:\\Program Files\\PowerShell\\7\\pwsh.exe"),
+            Some(Shell::Pwsh)
+        );
+        assert_eq!(Shell::from_name("dash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_name("make"), None);
+
+        let some = |name: &str| Some(name.to_string());
+        assert_eq!(
+            detect_from(some("nu"), some("/bin/bash")),
+            (Shell::Nu, Detection::ParentProcess("nu".to_string()))
+        );
+        assert_eq!(
+            detect_from(some("sudo"), some("/usr/bin/tcsh")),
+            (
+                Shell::Tcsh,
+                Detection::ShellVar("/usr/bin/tcsh".to_string())
+            )
+        );
+        assert_eq!(
+            detect_from(None, some("/opt/unknown")),
+            (Shell::Bash, Detection::Fallback)
+        );
+        assert_eq!(Shell::Elvish.to_string(), "elvish");
+    }
+
+    #[test]
+    fn test_script() {
+        let vars = vec![
+            ("PORT".to_string(), "3000".to_string()),
+            ("NAME".to_string(), "o'clock".to_string()),
+        ];
+        assert_eq!(
+            Shell::Bash.script(&vars),
+            "export PORT='3000'\nexport NAME='o'\"'\"'clock'\n"
+        );
+        assert_eq!(
+            Shell::Fish.script(&vars),
+            "set -gx PORT '3000'\nset -gx NAME 'o\\'clock'\n"
+        );
+        assert_eq!(
+            Shell::Nu.script(&vars),
+            "load-env {\n    \"PORT\": \"3000\"\n    \"NAME\": \"o'clock\"\n}\n"
+        );
+        assert_eq!(Shell::Nu.script(&[]), "load-env {\n}\n");
+        assert_eq!(
+            Shell::Pwsh.script(&vars),
+            "$env:PORT = '3000'\n$env:NAME = 'o''clock'\n"
+        );
+        assert_eq!(
+            Shell::Elvish.script(&vars),
+            "set-env PORT '3000'\nset-env NAME 'o''clock'\n"
+        );
+        assert_eq!(
+            Shell::Tcsh.script(&vars),
+            "setenv PORT '3000'\nsetenv NAME 'o'\\''clock'\n"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use crate::network;
+use crate::usage::{self, Usage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+const SYSTEM_PROMPT: &str = "You are a professional translator. Translate the following Chinese text to English. \
+Respond with a single JSON object and nothing else, using these fields: \
+\"translation\" (the primary English translation), \
+\"alternatives\" (an array of other acceptable translations, may be empty), \
+\"part_of_speech\" (an abbreviation such as \"n.\", \"v.\" or \"adj.\", or null for phrases and sentences), \
+\"example\" (a short English example sentence using the translation, or null), \
+\"confidence\" (a number between 0 and 1). \
+Copy markers such as ⟦0⟧ unchanged; they stand for code, names or numbers.";
+
+const BACK_TRANSLATION_PROMPT: &str = "You are a professional translator. Translate the following English text to Chinese. \
+Respond with the Chinese translation only, without explanations.";
+
+const PASSAGE_PROMPT: &str = "You are a professional translator. Translate the Chinese text inside <translate> tags to English. \
+Text inside <context> tags comes from the same document and is only there for continuity; do not translate it. \
+Keep the paragraph breaks, and copy markers such as ⟦0⟧ unchanged. Respond with the English translation only, without tags or explanations.";
+
+const LINES_PROMPT: &str = "You are a professional translator. The input is a JSON object. Translate each Chinese string in its \"items\" array to English. \
+\"context\" holds the text just before the items and is only there for continuity; do not translate it. \
+Keep line breaks inside an item, and keep markup such as <i>, {\\an8}, %s, {name} or ⟦0⟧ unchanged. \
+Respond with a single JSON object {\"translations\": [...]} holding exactly one English string per item, in the same order.";
+
+// AI translation service implementation
+#[derive(Clone)]
+pub struct AITranslationService {
+    api_key: String,
+    base_url: String,
+    model: String,
+    json_mode: bool,
+    client: reqwest::Client,
+}
+
+impl AITranslationService {
+    pub fn new() -> Result<Self> {
+        // Get API key from environment
+        let api_key = env::var("OPENAI_API_KEY").map_err(|_| {
+            Error::Config("OPENAI_API_KEY environment variable not set".to_string())
+        })?;
+
+        // Get base URL from environment or use default
+        let base_url = env::var("OPENAI_BASE_URL")
+            .unwrap_or_else(|_| "https://api.openai.com/v1/".to_string());
+
+        // Get model from environment or use default
+        let model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
+
+        // JSON mode is on unless explicitly disabled for providers that reject `response_format`
+        let json_mode = env::var("OPENAI_JSON_MODE")
+            .map(|v| !matches!(v.to_lowercase().as_str(), "0" | "false" | "off" | "no"))
+            .unwrap_or(true);
+
+        Ok(Self {
+            json_mode,
+            ..Self::with_endpoint(&base_url, &api_key, &model)?
+        })
+    }
+
+    /// Create a service for an explicit endpoint instead of the environment, e.g. a
+    /// local mock server in tests. Requests use the configured proxy and TLS settings.
+    pub fn with_endpoint(base_url: &str, api_key: &str, model: &str) -> Result<Self> {
+        Ok(Self {
+            api_key: api_key.to_string(),
+            base_url: base_url.to_string(),
+            model: model.to_string(),
+            json_mode: true,
+            client: network::client()?,
+        })
+    }
+
+    /// Send requests with `client`, e.g. one set up for a proxy by `network::client`.
+    #[cfg(test)]
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Create a service for a named model. Names are looked up in `~/.translate/models.json`
+    /// so models from different providers can be used side by side; a name that isn't
+    /// listed there is treated as a model of the default `OPENAI_*` provider.
+    pub fn for_model(name: &str) -> Result<Self> {
+        let profiles = load_profiles()?;
+        let Some(profile) = profiles.get(name) else {
+            let mut service = Self::new()?;
+            service.model = name.to_string();
+            return Ok(service);
+        };
+
+        let key_var = profile.api_key_env.as_deref().unwrap_or("OPENAI_API_KEY");
+        let api_key = env::var(key_var).map_err(|_| {
+            Error::Config(format!(
+                "{} environment variable not set (model '{}')",
+                key_var, name
+            ))
+        })?;
+        let base_url = match &profile.base_url {
+            Some(url) => url.clone(),
+            None => env::var("OPENAI_BASE_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1/".to_string()),
+        };
+
+        let model = profile.model.as_deref().unwrap_or(name);
+        Ok(Self {
+            json_mode: profile.json_mode.unwrap_or(true),
+            ..Self::with_endpoint(&base_url, &api_key, model)?
+        })
+    }
+}
+
+// The API key is left out so it can't end up in logs
+impl std::fmt::Debug for AITranslationService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AITranslationService")
+            .field("base_url", &self.base_url)
+            .field("model", &self.model)
+            .field("json_mode", &self.json_mode)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A named model in `models.json`, e.g.
+/// `{"glm": {"base_url": "https://open.bigmodel.cn/api/paas/v4/", "api_key_env": "GLM_API_KEY", "model": "glm-4.5"}}`.
+#[derive(Deserialize)]
+struct ModelProfile {
+    base_url: Option<String>,
+    api_key_env: Option<String>,
+    model: Option<String>,
+    json_mode: Option<bool>,
+}
+
+fn load_profiles() -> Result<HashMap<String, ModelProfile>> {
+    let path = crate::data_dir()?.join("models.json");
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let data = fs::read_to_string(&path)?;
+    serde_json::from_str(&data)
+        .map_err(|e| Error::Config(format!("Invalid {}: {}", path.display(), e)))
+}
+
+#[derive(serde::Serialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(serde::Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(serde::Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatChoice {
+    message: ChatResponseMessage,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatResponseMessage {
+    content: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+/// The JSON object the model is asked to return.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct AIResult {
+    #[serde(alias = "primary", alias = "english")]
+    translation: String,
+    alternatives: Vec<String>,
+    #[serde(alias = "pos", alias = "partOfSpeech")]
+    part_of_speech: Option<String>,
+    #[serde(alias = "example_sentence")]
+    example: Option<String>,
+    confidence: Option<serde_json::Value>,
+}
+
+impl AITranslationService {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Translate `text`, returning the entry and the token usage reported by the API.
+    pub async fn translate(&self, text: &str) -> Result<(Entry, Option<Usage>)> {
+        let (content, usage) = self.complete_json(SYSTEM_PROMPT, text).await?;
+        Ok((parse_response(text, &content), usage))
+    }
+
+    /// Translate several short texts, such as subtitle cues, in one request.
+    /// `context` is the source text just before them.
+    pub async fn translate_lines(
+        &self,
+        items: &[String],
+        context: &[String],
+    ) -> Result<(Vec<String>, Option<Usage>)> {
+        let input = serde_json::json!({ "context": context, "items": items }).to_string();
+        let (content, usage) = self.complete_json(LINES_PROMPT, &input).await?;
+        let translations = parse_translations(&content)
+            .filter(|translations| translations.len() == items.len())
+            .ok_or_else(|| {
+                Error::Parse(format!(
+                    "Expected {} translations in the API response",
+                    items.len()
+                ))
+            })?;
+        Ok((translations, usage))
+    }
+
+    /// Translate English `text` back to Chinese, as plain text.
+    pub async fn back_translate(&self, text: &str) -> Result<(String, Option<Usage>)> {
+        let (content, usage) = self.complete(BACK_TRANSLATION_PROMPT, text, false).await?;
+        Ok((strip_chatter(&content), usage))
+    }
+
+    /// Translate one chunk of a longer text as plain text, with the text around it as context.
+    pub async fn translate_passage(
+        &self,
+        text: &str,
+        before: &str,
+        after: &str,
+    ) -> Result<(String, Option<Usage>)> {
+        let mut message = String::new();
+        if !before.is_empty() {
+            message.push_str(&format!("<context>{}</context>\n", before));
+        }
+        message.push_str(&format!("<translate>{}</translate>", text));
+        if !after.is_empty() {
+            message.push_str(&format!("\n<context>{}</context>", after));
+        }
+        let (content, usage) = self.complete(PASSAGE_PROMPT, &message, false).await?;
+        let content = content
+            .replace("<translate>", "")
+            .replace("</translate>", "");
+        Ok((content.trim().to_string(), usage))
+    }
+
+    /// Ask for a JSON reply, in JSON mode if it is enabled.
+    async fn complete_json(
+        &self,
+        system_prompt: &str,
+        text: &str,
+    ) -> Result<(String, Option<Usage>)> {
+        match self.complete(system_prompt, text, self.json_mode).await {
+            // Some OpenAI-compatible providers reject `response_format` as a bad request;
+            // retry once without it. Other client errors such as 401 or 429 are final.
+            Err(Error::ApiStatus {
+                status: 400 | 422, ..
+            }) if self.json_mode => self.complete(system_prompt, text, false).await,
+            result => result,
+        }
+    }
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        text: &str,
+        json_mode: bool,
+    ) -> Result<(String, Option<Usage>)> {
+        // Create the request
+        let request = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                ChatMessage {
+                    role: "system".to_string(),
+                    content: system_prompt.to_string(),
+                },
+                ChatMessage {
+                    role: "user".to_string(),
+                    content: text.to_string(),
+                },
+            ],
+            response_format: json_mode.then(|| ResponseFormat {
+                kind: "json_object".to_string(),
+            }),
+        };
+
+        // Make the HTTP request
+        let url = format!("{}chat/completions", self.base_url);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Content-Type", "application/json")
+            .json(&request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(Error::ApiStatus {
+                status: status.as_u16(),
+                body: error_text,
+            });
+        }
+
+        let response_body: ChatResponse = response.json().await?;
+        let translation = response_body
+            .choices
+            .first()
+            .map(|choice| &choice.message.content)
+            .ok_or_else(|| Error::Parse("No translation received from API".to_string()))?;
+
+        // A failure to write the ledger shouldn't cost the user their translation
+        if let Some(usage) = &response_body.usage
+            && let Err(e) = usage::record(&self.model, usage)
+        {
+            eprintln!("Warning: failed to record token usage: {}", e);
+        }
+
+        Ok((translation.clone(), response_body.usage))
+    }
+}
+
+/// Turn the model's reply into an entry. JSON is preferred, but models without
+/// JSON mode may wrap it in code fences or prose, or ignore the format entirely.
+fn parse_response(source: &str, content: &str) -> Entry {
+    if let Some(result) = extract_json(content)
+        && !result.translation.trim().is_empty()
+    {
+        return Entry {
+            headword: result.translation.trim().to_string(),
+            part_of_speech: result
+                .part_of_speech
+                .map(|pos| pos.trim().to_string())
+                .filter(|pos| !pos.is_empty()),
+            gloss: Some(source.to_string()),
+            alternatives: result
+                .alternatives
+                .into_iter()
+                .map(|alt| alt.trim().to_string())
+                .filter(|alt| !alt.is_empty())
+                .collect(),
+            example: result
+                .example
+                .map(|example| example.trim().to_string())
+                .filter(|example| !example.is_empty()),
+            confidence: result.confidence.as_ref().and_then(parse_confidence),
+            ..Default::default()
+        };
+    }
+
+    Entry {
+        headword: strip_chatter(content),
+        gloss: Some(source.to_string()),
+        ..Default::default()
+    }
+}
+
+fn extract_json(content: &str) -> Option<AIResult> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    serde_json::from_str(&content[start..=end]).ok()
+}
+
+/// The strings of `{"translations": [...]}`, or of a bare array.
+fn parse_translations(content: &str) -> Option<Vec<String>> {
+    let value: serde_json::Value = match (content.find('{'), content.find('[')) {
+        (Some(start), bracket) if bracket.is_none_or(|bracket| start < bracket) => {
+            let end = content.rfind('}')?;
+            serde_json::from_str::<serde_json::Value>(content.get(start..=end)?)
+                .ok()?
+                .get("translations")?
+                .clone()
+        }
+        (_, Some(start)) => serde_json::from_str(content.get(start..=content.rfind(']')?)?).ok()?,
+        _ => return None,
+    };
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(|s| s.trim().to_string()))
+        .collect()
+}
+
+/// Accept 0.87, 87, "0.87" or "87%".
+fn parse_confidence(value: &serde_json::Value) -> Option<f32> {
+    let number = match value {
+        serde_json::Value::Number(n) => n.as_f64()?,
+        serde_json::Value::String(s) => s.trim().trim_end_matches('%').trim().parse().ok()?,
+        _ => return None,
+    };
+    let number = if number > 1.0 { number / 100.0 } else { number };
+    Some(number.clamp(0.0, 1.0) as f32)
+}
+
+/// Remove code fences, lead-ins such as "Here is the translation:" and surrounding quotes.
+fn strip_chatter(content: &str) -> String {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("```"))
+        .collect();
+
+    let body: Vec<&str> = match lines.split_first() {
+        Some((first, rest)) if first.ends_with(':') && !rest.is_empty() => rest.to_vec(),
+        _ => lines,
+    };
+
+    let mut text = body.join("\n");
+    if let Some((label, rest)) = text.split_once(':') {
+        let label = label.to_lowercase();
+        if !label.contains('\n') && (label.contains("translation") || label.starts_with("english"))
+        {
+            text = rest.trim().to_string();
+        }
+    }
+
+    text.trim_matches(|c| c == '"' || c == '“' || c == '”')
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_response() {
+        let content = r#"```json
+{"translation": "clockmaker", "alternatives": ["clock-maker", "horologist"], "part_of_speech": "n.", "example": "The clockmaker fixed my watch.", "confidence": 0.9}
+```"#;
+        let entry = parse_response("制造或修理钟表者", content);
+        assert_eq!(entry.headword, "clockmaker");
+        assert_eq!(entry.alternatives, vec!["clock-maker", "horologist"]);
+        assert_eq!(entry.part_of_speech.as_deref(), Some("n."));
+        assert_eq!(entry.gloss.as_deref(), Some("制造或修理钟表者"));
+        assert_eq!(
+            entry.example.as_deref(),
+            Some("The clockmaker fixed my watch.")
+        );
+        assert_eq!(entry.confidence, Some(0.9));
+    }
+
+    #[test]
+    fn test_parse_free_form_response() {
+        let entry = parse_response("你好", "Here is the translation:\n\"Hello\"");
+        assert_eq!(entry.headword, "Hello");
+        assert!(entry.alternatives.is_empty());
+
+        let entry = parse_response("你好", "Translation: Hello");
+        assert_eq!(entry.headword, "Hello");
+    }
+
+    #[test]
+    fn test_parse_translations() {
+        assert_eq!(
+            parse_translations("```json\n{\"translations\": [\"Hello\", \" Bye \"]}\n```"),
+            Some(vec!["Hello".to_string(), "Bye".to_string()])
+        );
+        assert_eq!(
+            parse_translations("[\"Hello\"]"),
+            Some(vec!["Hello".to_string()])
+        );
+        assert_eq!(parse_translations("{\"translations\": [1]}"), None);
+        assert_eq!(parse_translations("Hello"), None);
+    }
+
+    #[test]
+    fn test_parse_confidence() {
+        assert_eq!(parse_confidence(&serde_json::json!(0.5)), Some(0.5));
+        assert_eq!(parse_confidence(&serde_json::json!(80)), Some(0.8));
+        assert_eq!(parse_confidence(&serde_json::json!("75%")), Some(0.75));
+        assert_eq!(parse_confidence(&serde_json::json!(null)), None);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::chinese::is_han;
+use crate::dictionary::{Dictionary, DomainFilter, Match};
+use crate::entry::split_part_of_speech;
+use crate::error::{Error, Result};
+use crate::history::History;
+use rusqlite::Connection;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::io::Write;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+/// The note fields, in order. The first one is what Anki sorts and checks duplicates by.
+const FIELDS: [&str; 3] = ["Chinese", "English", "Part of speech"];
+
+const CSS: &str = ".card { font-family: sans-serif; font-size: 24px; text-align: center; }\n.pos { color: #888; font-size: 18px; }";
+
+/// One vocabulary note; Anki makes a Chinese → English and an English → Chinese card of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub chinese: String,
+    pub english: String,
+    pub part_of_speech: Option<String>,
+    /// The domain tags of the dictionary the word came from
+    pub tags: Vec<String>,
+}
+
+impl Note {
+    fn from_match(chinese: Option<&str>, found: &Match) -> Self {
+        let (part_of_speech, gloss) = split_part_of_speech(found.gloss);
+        Self {
+            chinese: chinese.map_or(gloss, str::to_string),
+            english: found.headword.to_string(),
+            part_of_speech,
+            tags: found.source.domains.clone(),
+        }
+    }
+}
+
+/// The recorded lookups, with the part of speech and tags of the dictionary entry when
+/// the translation is a headword.
+pub fn notes_from_history(history: &History, dictionary: &Dictionary) -> Vec<Note> {
+    history
+        .items
+        .iter()
+        .map(|item| match dictionary.entry(&item.translation) {
+            Some(found) => Note::from_match(Some(&item.source), &found),
+            None => Note {
+                chinese: item.source.clone(),
+                english: item.translation.clone(),
+                part_of_speech: None,
+                tags: Vec::new(),
+            },
+        })
+        .collect()
+}
+
+/// Every headword, or with `--domain-only` those of the dictionaries with one of the tags.
+pub fn notes_from_dictionary(dictionary: &Dictionary, filter: &DomainFilter) -> Vec<Note> {
+    let mut headwords: Vec<&str> = dictionary.iter().map(|(headword, _)| headword).collect();
+    headwords.sort_unstable();
+    headwords
+        .into_iter()
+        .filter_map(|headword| dictionary.entry(headword))
+        .filter(|found| {
+            !filter.restrict || found.source.domains.iter().any(|t| filter.tags.contains(t))
+        })
+        .map(|found| Note::from_match(None, &found))
+        .collect()
+}
+
+/// A word list with one word per line: an English headword, a Chinese word looked up
+/// by meaning, or `中文<TAB>English` taken as it is. Blank lines and `#` comments are
+/// skipped; words the dictionaries don't have are reported and left out.
+pub fn notes_from_list(list: &str, dictionary: &Dictionary, filter: &DomainFilter) -> Vec<Note> {
+    let mut notes = Vec::new();
+    for line in list.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((chinese, english)) = line.split_once('\t') {
+            let (chinese, english) = (chinese.trim(), english.trim());
+            notes.push(match dictionary.entry(english) {
+                Some(found) => Note::from_match(Some(chinese), &found),
+                None => Note {
+                    chinese: chinese.to_string(),
+                    english: english.to_string(),
+                    part_of_speech: None,
+                    tags: Vec::new(),
+                },
+            });
+            continue;
+        }
+        let note = if line.chars().any(is_han) {
+            let found = dictionary.lookup(line, filter).into_iter().next();
+            found.map(|found| Note::from_match(Some(line), &found))
+        } else {
+            dictionary
+                .entry(line)
+                .map(|found| Note::from_match(None, &found))
+        };
+        match note {
+            Some(note) => notes.push(note),
+            None => eprintln!("Warning: '{}' is not in the dictionaries, skipped", line),
+        }
+    }
+    notes
+}
+
+/// Build an Anki package: a zip with the SQLite collection and an empty media list.
+/// `now` is in milliseconds and seeds the note and card ids.
+pub fn package(notes: &[Note], deck: &str, now: i64) -> Result<Vec<u8>> {
+    let collection = collection(notes, deck, now).map_err(sqlite_error)?;
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    zip.start_file("collection.anki2", options)
+        .map_err(zip_error)?;
+    zip.write_all(&collection)?;
+    zip.start_file("media", options).map_err(zip_error)?;
+    zip.write_all(b"{}")?;
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+/// The collection in the schema Anki 2.1 imports (version 11).
+fn collection(notes: &[Note], deck: &str, now: i64) -> rusqlite::Result<Vec<u8>> {
+    let seconds = now / 1000;
+    let model_id = stable_id("translate vocabulary");
+    let deck_id = stable_id(deck);
+
+    let fields: Vec<_> = FIELDS
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({ "name": name, "ord": ord, "sticky": false, "rtl": false, "font": "Arial", "size": 20, "media": [] })
+        })
+        .collect();
+    let answer_pos =
+        "{{#Part of speech}}<div class=pos>{{Part of speech}}</div>{{/Part of speech}}";
+    let templates = json!([
+        {
+            "name": "Chinese → English", "ord": 0, "did": null, "bqfmt": "", "bafmt": "",
+            "qfmt": "{{Chinese}}",
+            "afmt": format!("{{{{FrontSide}}}}<hr id=answer>{{{{English}}}}{}", answer_pos),
+        },
+        {
+            "name": "English → Chinese", "ord": 1, "did": null, "bqfmt": "", "bafmt": "",
+            "qfmt": format!("{{{{English}}}}{}", answer_pos),
+            "afmt": "{{FrontSide}}<hr id=answer>{{Chinese}}",
+        },
+    ]);
+    let model = json!({
+        "id": model_id, "name": "translate vocabulary", "type": 0, "mod": seconds, "usn": -1,
+        "sortf": 0, "did": deck_id, "tmpls": templates, "flds": fields, "css": CSS,
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}", "tags": [], "vers": [],
+        "req": [[0, "all", [0]], [1, "all", [1]]],
+    });
+    let deck_json = |id: i64, name: &str| {
+        json!({
+            "id": id, "name": name, "desc": "", "mod": seconds, "usn": -1, "collapsed": false,
+            "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0], "timeToday": [0, 0],
+            "conf": 1, "dyn": 0, "extendNew": 10, "extendRev": 50,
+        })
+    };
+    let decks =
+        json!({ "1": deck_json(1, "Default"), deck_id.to_string(): deck_json(deck_id, deck) });
+    let deck_config = json!({ "1": {
+        "id": 1, "name": "Default", "mod": 0, "usn": 0, "maxTaken": 60, "timer": 0,
+        "autoplay": true, "replayq": true, "dyn": false,
+        "new": { "delays": [1, 10], "ints": [1, 4, 7], "initialFactor": 2500, "order": 1, "perDay": 20, "bury": true, "separate": true },
+        "rev": { "perDay": 200, "ease4": 1.3, "fuzz": 0.05, "maxIvl": 36500, "bury": true, "minSpace": 1 },
+        "lapse": { "delays": [10], "mult": 0, "minInt": 1, "leechFails": 8, "leechAction": 0 },
+    }});
+    let config = json!({
+        "nextPos": notes.len() + 1, "estTimes": true, "activeDecks": [1], "sortType": "noteFld",
+        "timeLim": 0, "sortBackwards": false, "addToCur": true, "curDeck": 1, "newBury": true,
+        "newSpread": 0, "dueCounts": true, "curModel": model_id, "collapseTime": 1200,
+    });
+
+    let db = Connection::open_in_memory()?;
+    db.execute_batch(SCHEMA)?;
+    db.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        rusqlite::params![
+            seconds,
+            now,
+            config.to_string(),
+            json!({ model_id.to_string(): model }).to_string(),
+            decks.to_string(),
+            deck_config.to_string(),
+        ],
+    )?;
+    for (i, note) in notes.iter().enumerate() {
+        let i = i as i64;
+        let fields = [
+            escape(&note.chinese),
+            escape(&note.english),
+            escape(note.part_of_speech.as_deref().unwrap_or_default()),
+        ];
+        let tags: Vec<String> = note.tags.iter().map(|tag| tag.replace(' ', "_")).collect();
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!(" {} ", tags.join(" "))
+        };
+        db.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+            rusqlite::params![
+                now + i,
+                guid(note),
+                model_id,
+                seconds,
+                tags,
+                fields.join("\x1f"),
+                fields[0],
+                checksum(&note.chinese),
+            ],
+        )?;
+        for ord in 0..2 {
+            // New cards are due in the order of their notes
+            db.execute(
+                "INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, 0, 0, ?6, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                rusqlite::params![now + i * 2 + ord, now + i, deck_id, ord, seconds, i + 1],
+            )?;
+        }
+    }
+    Ok(db.serialize("main")?.to_vec())
+}
+
+const SCHEMA: &str = "
+CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null, scm integer not null, ver integer not null, dty integer not null, usn integer not null, ls integer not null, conf text not null, models text not null, decks text not null, dconf text not null, tags text not null);
+CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null, mod integer not null, usn integer not null, tags text not null, flds text not null, sfld integer not null, csum integer not null, flags integer not null, data text not null);
+CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null, ord integer not null, mod integer not null, usn integer not null, type integer not null, queue integer not null, due integer not null, ivl integer not null, factor integer not null, reps integer not null, lapses integer not null, left integer not null, odue integer not null, odid integer not null, flags integer not null, data text not null);
+CREATE TABLE revlog (id integer primary key, cid integer not null, usn integer not null, ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null, type integer not null);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn ON notes (usn);
+CREATE INDEX ix_cards_usn ON cards (usn);
+CREATE INDEX ix_revlog_usn ON revlog (usn);
+CREATE INDEX ix_cards_nid ON cards (nid);
+CREATE INDEX ix_cards_sched ON cards (did, queue, due);
+CREATE INDEX ix_revlog_cid ON revlog (cid);
+CREATE INDEX ix_notes_csum ON notes (csum);
+";
+
+fn sha1_hex(text: &str) -> String {
+    Sha1::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// An id derived from a name, so exporting the same deck again updates it in Anki
+/// instead of creating a copy. Kept below 2^53 for Anki's JavaScript.
+fn stable_id(name: &str) -> i64 {
+    i64::from_str_radix(&sha1_hex(name)[..13], 16).unwrap_or(1)
+}
+
+/// The note id Anki matches re-imported notes by, derived from the word pair.
+fn guid(note: &Note) -> String {
+    sha1_hex(&format!("{}\x1f{}", note.chinese, note.english))[..16].to_string()
+}
+
+/// Anki's duplicate check: the first 8 hex digits of the SHA-1 of the first field.
+fn checksum(field: &str) -> i64 {
+    i64::from_str_radix(&sha1_hex(field)[..8], 16).unwrap_or(0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn sqlite_error(e: rusqlite::Error) -> Error {
+    Error::Io(std::io::Error::other(format!(
+        "Failed to build the Anki collection: {}",
+        e
+    )))
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::Io(std::io::Error::other(format!(
+        "Failed to write the Anki package: {}",
+        e
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_dictionary;
+    use std::io::Read;
+
+    #[test]
+    fn test_notes() {
+        let dictionary = fixture_dictionary();
+        let filter = DomainFilter {
+            tags: vec!["computing".to_string()],
+            restrict: true,
+        };
+        let notes = notes_from_dictionary(&dictionary, &filter);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(
+            notes[0],
+            Note {
+                chinese: "病毒".to_string(),
+                english: "computer virus".to_string(),
+                part_of_speech: Some("n.".to_string()),
+                tags: vec!["computing".to_string()],
+            }
+        );
+
+        let notes = notes_from_list(
+            "# 词表\nclock\n恶意软件\n钟表匠\tclockmaker\nnot-a-word\n",
+            &dictionary,
+            &DomainFilter::default(),
+        );
+        let pairs: Vec<(&str, &str)> = notes
+            .iter()
+            .map(|note| (note.chinese.as_str(), note.english.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("时钟；钟", "clock"),
+                ("恶意软件", "malware"),
+                ("钟表匠", "clockmaker")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_package() {
+        let notes = vec![Note {
+            chinese: "病毒".to_string(),
+            english: "computer virus".to_string(),
+            part_of_speech: Some("n.".to_string()),
+            tags: vec!["computing".to_string()],
+        }];
+        let bytes = package(&notes, "术语", 1_700_000_000_000).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut media = String::new();
+        archive
+            .by_name("media")
+            .unwrap()
+            .read_to_string(&mut media)
+            .unwrap();
+        assert_eq!(media, "{}");
+
+        let file = archive.by_name("collection.anki2").unwrap();
+        let size = file.size() as usize;
+        let mut db = Connection::open_in_memory().unwrap();
+        db.deserialize_read_exact("main", file, size, true).unwrap();
+        let (fields, tags): (String, String) = db
+            .query_row("SELECT flds, tags FROM notes", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(fields, "病毒\x1fcomputer virus\x1fn.");
+        assert_eq!(tags, " computing ");
+        let cards: i64 = db
+            .query_row("SELECT count(*) FROM cards", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cards, 2);
+        let decks: String = db
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .unwrap();
+        assert!(decks.contains("术语"));
+    }
+}
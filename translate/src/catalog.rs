@@ -0,0 +1,546 @@
+use crate::ai::AITranslationService;
+use crate::chinese::is_han;
+use crate::dictionary::Dictionary;
+use crate::error::{Error, Result};
+use crate::memory::TranslationMemory;
+use crate::placeholder;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// A gettext PO file. Entries keep their original lines so only the ones that get
+/// filled are rewritten.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoFile {
+    entries: Vec<PoEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PoEntry {
+    lines: Vec<String>,
+    msgid: String,
+    msgid_plural: Option<String>,
+    msgstr: Vec<String>,
+    /// Index of the first `msgstr` line, where rewriting starts
+    msgstr_line: Option<usize>,
+}
+
+impl PoEntry {
+    fn is_untranslated(&self) -> bool {
+        !self.msgid.is_empty()
+            && self.msgstr_line.is_some()
+            && self.msgstr.iter().all(String::is_empty)
+            && self.msgid.chars().any(is_han)
+    }
+
+    /// Replace the msgstr lines and add the fuzzy flag.
+    fn fill(&mut self, singular: &str, plural: &str) {
+        let Some(msgstr_line) = self.msgstr_line else {
+            return;
+        };
+        let mut lines: Vec<String> = self.lines[..msgstr_line].to_vec();
+        match lines.iter_mut().find(|line| line.starts_with("#,")) {
+            Some(flags) => {
+                if !flags.split([',', ' ']).any(|flag| flag == "fuzzy") {
+                    *flags = format!("#, fuzzy,{}", &flags[2..]);
+                }
+            }
+            None => {
+                let at = lines
+                    .iter()
+                    .position(|line| !line.starts_with('#'))
+                    .unwrap_or(lines.len());
+                lines.insert(at, "#, fuzzy".to_string());
+            }
+        }
+
+        let msgstr_at = lines.len();
+        if self.msgid_plural.is_some() {
+            let forms = self.msgstr.len().max(2);
+            self.msgstr = (0..forms)
+                .map(|n| if n == 0 { singular } else { plural }.to_string())
+                .collect();
+            for (n, text) in self.msgstr.iter().enumerate() {
+                lines.extend(po_field(&format!("msgstr[{}]", n), text));
+            }
+        } else {
+            self.msgstr = vec![singular.to_string()];
+            lines.extend(po_field("msgstr", singular));
+        }
+        self.msgstr_line = Some(msgstr_at);
+        self.lines = lines;
+    }
+}
+
+pub fn parse_po(content: &str) -> PoFile {
+    let mut entries = Vec::new();
+    let mut block: Vec<String> = Vec::new();
+    for line in content.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            block.push(line.to_string());
+        } else if !block.is_empty() {
+            entries.push(parse_po_entry(std::mem::take(&mut block)));
+        }
+    }
+    PoFile { entries }
+}
+
+fn parse_po_entry(lines: Vec<String>) -> PoEntry {
+    let mut entry = PoEntry {
+        lines: Vec::new(),
+        msgid: String::new(),
+        msgid_plural: None,
+        msgstr: Vec::new(),
+        msgstr_line: None,
+    };
+    // Which string continuation lines belong to
+    let mut field: Option<(&str, usize)> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            field = None;
+            continue;
+        }
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword, rest)) if !line.starts_with('"') => (keyword, rest.trim()),
+            _ => ("", line),
+        };
+        let value = unquote(rest);
+        match keyword {
+            "" => match field {
+                Some(("msgid", _)) => entry.msgid.push_str(&value),
+                Some(("msgid_plural", _)) => {
+                    entry.msgid_plural.get_or_insert_default().push_str(&value)
+                }
+                Some(("msgstr", n)) => entry.msgstr[n].push_str(&value),
+                _ => {}
+            },
+            "msgid" => {
+                entry.msgid = value;
+                field = Some(("msgid", 0));
+            }
+            "msgid_plural" => {
+                entry.msgid_plural = Some(value);
+                field = Some(("msgid_plural", 0));
+            }
+            keyword if keyword.starts_with("msgstr") => {
+                entry.msgstr_line.get_or_insert(i);
+                entry.msgstr.push(value);
+                field = Some(("msgstr", entry.msgstr.len() - 1));
+            }
+            _ => field = None,
+        }
+    }
+    entry.lines = lines;
+    entry
+}
+
+fn unquote(quoted: &str) -> String {
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or("");
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('r') => value.push('\r'),
+            Some(other) => value.push(other),
+            None => {}
+        }
+    }
+    value
+}
+
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// A keyword and its string, split after each `\n` the way gettext tools write them.
+fn po_field(keyword: &str, value: &str) -> Vec<String> {
+    let parts: Vec<&str> = value.split_inclusive('\n').collect();
+    if parts.len() <= 1 {
+        return vec![format!("{} {}", keyword, quote(value))];
+    }
+    let mut lines = vec![format!("{} \"\"", keyword)];
+    lines.extend(parts.into_iter().map(quote));
+    lines
+}
+
+impl std::fmt::Display for PoFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            for line in &entry.lines {
+                writeln!(f, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merge a JSON locale file with its Chinese source, in the source's key order.
+/// Strings that are missing or empty in `target` are collected in `pending` by
+/// their path and left empty for now. Keys only `target` has are kept at the end.
+pub fn merge_json(
+    source: &Value,
+    target: Option<&Value>,
+    pending: &mut Vec<(Vec<String>, String)>,
+) -> Value {
+    merge_at(source, target, &mut Vec::new(), pending)
+}
+
+fn merge_at(
+    source: &Value,
+    target: Option<&Value>,
+    path: &mut Vec<String>,
+    pending: &mut Vec<(Vec<String>, String)>,
+) -> Value {
+    match source {
+        Value::Object(entries) => {
+            let target = target.and_then(Value::as_object);
+            let mut merged = Map::new();
+            for (key, value) in entries {
+                path.push(key.clone());
+                let existing = target.and_then(|target| target.get(key));
+                merged.insert(key.clone(), merge_at(value, existing, path, pending));
+                path.pop();
+            }
+            for (key, value) in target.into_iter().flatten() {
+                if !merged.contains_key(key) {
+                    merged.insert(key.clone(), value.clone());
+                }
+            }
+            Value::Object(merged)
+        }
+        Value::String(text) => match target {
+            Some(Value::String(existing)) if !existing.is_empty() => {
+                target.cloned().unwrap_or_default()
+            }
+            _ if text.chars().any(is_han) => {
+                pending.push((path.clone(), text.clone()));
+                Value::String(String::new())
+            }
+            _ => target.cloned().unwrap_or_else(|| source.clone()),
+        },
+        _ => target.cloned().unwrap_or_else(|| source.clone()),
+    }
+}
+
+/// The JSON Pointer of a key path, e.g. `/menu/file`.
+pub fn json_pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+pub fn set_json(value: &mut Value, path: &[String], text: &str) {
+    if let Some(slot) = value.pointer_mut(&json_pointer(path)) {
+        *slot = Value::String(text.to_string());
+    }
+}
+
+/// Where the machine-filled keys of a JSON catalog are listed, e.g. `en.json.fuzzy.json`.
+pub fn fuzzy_path(catalog: &Path) -> PathBuf {
+    let mut name = catalog.as_os_str().to_owned();
+    name.push(".fuzzy.json");
+    PathBuf::from(name)
+}
+
+/// JSON has no fuzzy flag, so machine-filled keys are kept in a sidecar file as JSON
+/// Pointers until someone reviews them and takes them out. The keys still listed in
+/// `previous` stay listed while `merged` has them, followed by the newly `filled` ones.
+pub fn fuzzy_list(merged: &Value, previous: Option<&Value>, filled: &[String]) -> Vec<String> {
+    let mut fuzzy: Vec<String> = previous
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .filter(|pointer| merged.pointer(pointer).is_some_and(|v| v != ""))
+        .map(str::to_string)
+        .collect();
+    for pointer in filled {
+        if !fuzzy.contains(pointer) {
+            fuzzy.push(pointer.clone());
+        }
+    }
+    fuzzy
+}
+
+/// How untranslated catalog strings are filled.
+pub struct Filler<'a> {
+    pub dictionary: &'a Dictionary,
+    pub memory: &'a TranslationMemory,
+    pub options: crate::TranslateOptions,
+    pub memory_threshold: f32,
+    pub batch_size: usize,
+}
+
+impl Filler<'_> {
+    /// Translate each text the same way as the command line: the translation memory,
+    /// then the exact dictionary definitions, then (with `--ai`) the AI service in batches. Placeholders
+    /// are protected from the AI service; a translation that lost one is left out.
+    pub async fn fill(&self, texts: &[String]) -> Result<Vec<Option<String>>> {
+        let mut translations: Vec<Option<String>> = Vec::new();
+        let mut remaining = Vec::new();
+        for (i, text) in texts.iter().enumerate() {
+            if let Some(entry) = self.memory.lookup(text, self.memory_threshold) {
+                translations.push(Some(entry.headword));
+                continue;
+            }
+            // A short string is part of many glosses, so only an exact definition will do
+            let options = crate::TranslateOptions {
+                use_ai: false,
+                exact: true,
+                ..self.options.clone()
+            };
+            match crate::translate(self.dictionary, text, &options).await {
+                Ok(entry) => translations.push(Some(entry.headword)),
+                Err(Error::NotFound(_)) => {
+                    translations.push(None);
+                    remaining.push(i);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if remaining.is_empty() || !self.options.use_ai {
+            return Ok(translations);
+        }
+
+        let service = match &self.options.ai_service {
+            Some(service) => service.clone(),
+            None => AITranslationService::new()?,
+        };
+        let protected: Vec<_> = remaining
+            .iter()
+            .map(|&i| placeholder::protect(&texts[i]))
+            .collect();
+        let items: Vec<String> = protected.iter().map(|p| p.text.clone()).collect();
+        let results = crate::chunk::translate_batches(
+            &service,
+            &items,
+            self.batch_size,
+            self.options.concurrency,
+        )
+        .await?;
+        for ((i, protected), result) in remaining.into_iter().zip(&protected).zip(results) {
+            translations[i] = protected.restore(&result);
+            if translations[i].is_none() {
+                eprintln!(
+                    "Warning: the translation of '{}' lost a placeholder, left empty",
+                    texts[i]
+                );
+            }
+        }
+        Ok(translations)
+    }
+}
+
+impl PoFile {
+    /// Fill the untranslated entries, marking them fuzzy. Returns the number of
+    /// entries filled and the number that were untranslated.
+    pub async fn fill(&mut self, filler: &Filler<'_>) -> Result<(usize, usize)> {
+        let untranslated: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| self.entries[i].is_untranslated())
+            .collect();
+        let mut texts = Vec::new();
+        for &i in &untranslated {
+            let entry = &self.entries[i];
+            texts.push(entry.msgid.clone());
+            texts.push(entry.msgid_plural.clone().unwrap_or_default());
+        }
+        // Singular-only entries have an empty plural, which needs no translation
+        let (wanted, positions): (Vec<String>, Vec<usize>) = texts
+            .iter()
+            .enumerate()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(n, text)| (text.clone(), n))
+            .unzip();
+        let mut results = vec![None; texts.len()];
+        for (n, translation) in positions.into_iter().zip(filler.fill(&wanted).await?) {
+            results[n] = translation;
+        }
+
+        let mut filled = 0;
+        for (k, &i) in untranslated.iter().enumerate() {
+            let entry = &mut self.entries[i];
+            let Some(singular) = results[2 * k].clone() else {
+                continue;
+            };
+            let plural = match &entry.msgid_plural {
+                Some(_) => match results[2 * k + 1].clone() {
+                    Some(plural) => plural,
+                    None => continue,
+                },
+                None => String::new(),
+            };
+            entry.fill(&singular, &plural);
+            filled += 1;
+        }
+        Ok((filled, untranslated.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, chat_reply, fixture_dictionary};
+    use serde_json::json;
+
+    const PO: &str = r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+#: src/main.c:10
+#, c-format
+msgid "你好，%s"
+msgstr ""
+
+msgid "钟表"
+msgstr "clock"
+
+msgid "删除了 {count} 个文件"
+msgid_plural "删除了 {count} 个文件"
+msgstr[0] ""
+msgstr[1] ""
+
+msgid ""
+"第一行\n"
+"第二行"
+msgstr ""
+"#;
+
+    #[test]
+    fn test_po_round_trip() {
+        let po = parse_po(PO);
+        assert_eq!(po.to_string(), PO);
+        assert_eq!(po.entries[4].msgid, "第一行\n第二行");
+        assert_eq!(po.entries.iter().filter(|e| e.is_untranslated()).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fill_po() {
+        let server = MockServer::start(vec![chat_reply(
+            r#"{"translations": ["Hello, ⟦0⟧", "Deleted ⟦0⟧ file", "Deleted ⟦0⟧ files", "First line\nSecond line"]}"#,
+        )])
+        .await;
+        let dictionary = fixture_dictionary();
+        let memory = TranslationMemory::default();
+        let filler = Filler {
+            dictionary: &dictionary,
+            memory: &memory,
+            options: crate::TranslateOptions {
+                use_ai: true,
+                ai_service: Some(server.service()),
+                ..Default::default()
+            },
+            memory_threshold: 1.0,
+            batch_size: 20,
+        };
+        let mut po = parse_po(PO);
+        assert_eq!(po.fill(&filler).await.unwrap(), (3, 3));
+
+        let output = po.to_string();
+        assert!(output.contains("#, fuzzy, c-format\nmsgid \"你好，%s\"\nmsgstr \"Hello, %s\"\n"));
+        assert!(output.contains("msgstr \"clock\"\n"));
+        assert!(output.contains("#, fuzzy\nmsgid \"删除了 {count} 个文件\""));
+        assert!(
+            output.contains(
+                "msgstr[0] \"Deleted {count} file\"\nmsgstr[1] \"Deleted {count} files\"\n"
+            )
+        );
+        assert!(output.contains("msgstr \"\"\n\"First line\\n\"\n\"Second line\"\n"));
+        // Filling again finds nothing to do
+        assert_eq!(parse_po(&output).fill(&filler).await.unwrap(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_fill_json() {
+        let source = json!({"greeting": "你好", "menu": {"file": "计算机", "count": "共 {{count}} 项"}, "ok": "OK"});
+        let target = json!({"menu": {"count": ""}, "greeting": "Hi", "extra": "kept"});
+        let mut pending = Vec::new();
+        let mut merged = merge_json(&source, Some(&target), &mut pending);
+        assert_eq!(
+            pending,
+            vec![
+                (
+                    vec!["menu".to_string(), "file".to_string()],
+                    "计算机".to_string()
+                ),
+                (
+                    vec!["menu".to_string(), "count".to_string()],
+                    "共 {{count}} 项".to_string()
+                ),
+            ]
+        );
+
+        // The dictionary has 计算机; the placeholder survives the AI translation
+        let server =
+            MockServer::start(vec![chat_reply(r#"{"translations": ["⟦0⟧ items"]}"#)]).await;
+        let dictionary = fixture_dictionary();
+        let memory = TranslationMemory::default();
+        let filler = Filler {
+            dictionary: &dictionary,
+            memory: &memory,
+            options: crate::TranslateOptions {
+                use_ai: true,
+                ai_service: Some(server.service()),
+                ..Default::default()
+            },
+            memory_threshold: 1.0,
+            batch_size: 20,
+        };
+        let texts: Vec<String> = pending.iter().map(|(_, text)| text.clone()).collect();
+        let translations = filler.fill(&texts).await.unwrap();
+        let mut filled = Vec::new();
+        for ((path, _), translation) in pending.iter().zip(translations) {
+            set_json(&mut merged, path, &translation.unwrap());
+            filled.push(json_pointer(path));
+        }
+        assert_eq!(
+            serde_json::to_string(&merged).unwrap(),
+            r#"{"greeting":"Hi","menu":{"file":"computer","count":"{{count}} items"},"ok":"OK","extra":"kept"}"#
+        );
+
+        // Unreviewed keys of an earlier run stay listed; keys that are gone are dropped
+        let previous = json!(["/greeting", "/removed"]);
+        assert_eq!(
+            fuzzy_list(&merged, Some(&previous), &filled),
+            vec!["/greeting", "/menu/file", "/menu/count"]
+        );
+        assert_eq!(
+            json_pointer(&["a/b".to_string(), "c~d".to_string()]),
+            "/a~1b/c~0d"
+        );
+        assert_eq!(
+            fuzzy_path(Path::new("locales/en.json")),
+            PathBuf::from("locales/en.json.fuzzy.json")
+        );
+
+        // 修 is part of the clockmaker gloss, which doesn't make it a translation
+        let local = Filler {
+            options: crate::TranslateOptions::default(),
+            ..filler
+        };
+        assert_eq!(
+            local
+                .fill(&["修".to_string(), "钟".to_string()])
+                .await
+                .unwrap(),
+            vec![None, Some("clock".to_string())]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+
+/// Bundled character tables, so annotation and conversion work offline.
+const PINYIN_TABLE: &str = include_str!("../data/pinyin.txt");
+const SCRIPT_TABLE: &str = include_str!("../data/script.txt");
+
+/// Reading of each character in tone-number form, e.g. `lv4`.
+static PINYIN: LazyLock<HashMap<char, &'static str>> = LazyLock::new(|| {
+    let mut readings = HashMap::new();
+    for (syllable, chars) in table_lines(PINYIN_TABLE) {
+        for c in chars.chars() {
+            readings.insert(c, syllable);
+        }
+    }
+    readings
+});
+
+/// Simplified to traditional, and traditional to simplified.
+static SCRIPT: LazyLock<(HashMap<char, char>, HashMap<char, char>)> = LazyLock::new(|| {
+    let mut to_traditional = HashMap::new();
+    let mut to_simplified = HashMap::new();
+    for (simplified, traditional) in table_lines(SCRIPT_TABLE) {
+        let Some(simplified) = simplified.chars().next() else {
+            continue;
+        };
+        let mut forms = traditional.chars();
+        if let Some(default) = forms.next()
+            && default != simplified
+        {
+            to_traditional.insert(simplified, default);
+            to_simplified.insert(default, simplified);
+        }
+        for form in forms {
+            to_simplified.insert(form, simplified);
+        }
+    }
+    (to_traditional, to_simplified)
+});
+
+fn table_lines(table: &'static str) -> impl Iterator<Item = (&'static str, &'static str)> {
+    table
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' '))
+}
+
+/// How pinyin readings are written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinyinStyle {
+    /// Tone marks, e.g. zhōng biǎo
+    Marks,
+    /// Tone numbers, e.g. zhong1 biao3
+    Numbers,
+}
+
+/// Which Chinese script to print.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    Simplified,
+    Traditional,
+}
+
+pub fn is_han(c: char) -> bool {
+    matches!(c, '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// CJK symbols and punctuation, full-width punctuation such as `，` and `：`, and the
+/// curly quotes, ellipsis and dash used in Chinese text. Full-width letters and digits
+/// are not punctuation.
+pub fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303F}' | '\u{FF00}'..='\u{FF0F}' | '\u{FF1A}'..='\u{FF20}' | '\u{FF3B}'..='\u{FF40}' | '\u{FF5B}'..='\u{FF65}' | '“' | '”' | '‘' | '’' | '…' | '—' | '·')
+}
+
+pub fn to_simplified(text: &str) -> String {
+    text.chars()
+        .map(|c| SCRIPT.1.get(&c).copied().unwrap_or(c))
+        .collect()
+}
+
+/// Character by character conversion, which picks the most common traditional form
+/// when a simplified character stands for several (e.g. 发 becomes 發, never 髮).
+pub fn to_traditional(text: &str) -> String {
+    text.chars()
+        .map(|c| SCRIPT.0.get(&c).copied().unwrap_or(c))
+        .collect()
+}
+
+/// Normalize looked-up text to the simplified script the dictionaries and memory use.
+pub fn normalize_input(text: &str) -> String {
+    to_simplified(text.trim())
+}
+
+/// Space-separated pinyin of the Chinese characters in `text`. Characters without a
+/// known reading are kept as they are.
+pub fn pinyin(text: &str, style: PinyinStyle) -> String {
+    text.chars()
+        .filter(|c| is_han(*c))
+        .map(|c| match PINYIN.get(&c) {
+            Some(syllable) => match style {
+                PinyinStyle::Marks => with_tone_mark(syllable),
+                PinyinStyle::Numbers => syllable.to_string(),
+            },
+            None => c.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Turn a tone-number syllable such as `lve4` into `lüè`.
+fn with_tone_mark(syllable: &str) -> String {
+    let (letters, tone) = match syllable.char_indices().last() {
+        Some((i, tone @ '1'..='5')) => (&syllable[..i], tone as usize - '0' as usize),
+        _ => (syllable, 5),
+    };
+    let letters = letters.replace('v', "ü");
+    if tone == 5 {
+        return letters;
+    }
+    // The mark goes on a or e, on the o of ou, and otherwise on the last vowel
+    let target = letters
+        .find('a')
+        .or_else(|| letters.find('e'))
+        .or_else(|| letters.find("ou"))
+        .or_else(|| letters.rfind(['i', 'o', 'u', 'ü']));
+    let Some(target) = target else {
+        return letters;
+    };
+    let mut marked = String::new();
+    for (i, c) in letters.char_indices() {
+        if i == target {
+            marked.push(mark(c, tone));
+        } else {
+            marked.push(c);
+        }
+    }
+    marked
+}
+
+fn mark(vowel: char, tone: usize) -> char {
+    let marks = match vowel {
+        'a' => ['ā', 'á', 'ǎ', 'à'],
+        'e' => ['ē', 'é', 'ě', 'è'],
+        'i' => ['ī', 'í', 'ǐ', 'ì'],
+        'o' => ['ō', 'ó', 'ǒ', 'ò'],
+        'u' => ['ū', 'ú', 'ǔ', 'ù'],
+        'ü' => ['ǖ', 'ǘ', 'ǚ', 'ǜ'],
+        _ => return vowel,
+    };
+    marks[tone - 1]
+}
+
+/// How Chinese text is shown: in which script, and whether runs of characters
+/// are followed by their pinyin, e.g. "钟表 (zhōng biǎo)".
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Display {
+    pub script: Option<Script>,
+    pub pinyin: Option<PinyinStyle>,
+}
+
+impl Display {
+    /// Only convert the script, for output such as JSON that must keep its values intact.
+    pub fn convert(&self, text: &str) -> String {
+        match self.script {
+            Some(Script::Simplified) => to_simplified(text),
+            Some(Script::Traditional) => to_traditional(text),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let text = self.convert(text);
+        let Some(style) = self.pinyin else {
+            return text;
+        };
+
+        let mut annotated = String::new();
+        let mut run = String::new();
+        for c in text.chars() {
+            if is_han(c) {
+                run.push(c);
+                continue;
+            }
+            self.flush_run(&mut annotated, &mut run, style);
+            annotated.push(c);
+        }
+        self.flush_run(&mut annotated, &mut run, style);
+        annotated
+    }
+
+    fn flush_run(&self, annotated: &mut String, run: &mut String, style: PinyinStyle) {
+        if run.is_empty() {
+            return;
+        }
+        annotated.push_str(run);
+        annotated.push_str(&format!(" ({})", pinyin(run, style)));
+        run.clear();
+    }
+}
+
+/// A writer that applies a `Display` to each line, for interactive output such as the quiz.
+pub struct Writer<W: Write> {
+    inner: W,
+    display: Display,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, display: Display) -> Self {
+        Self {
+            inner,
+            display,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_pending(&mut self, upto: usize) -> io::Result<()> {
+        let chunk: Vec<u8> = self.pending.drain(..upto).collect();
+        let text = String::from_utf8_lossy(&chunk);
+        self.inner.write_all(self.display.apply(&text).as_bytes())
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if let Some(newline) = self.pending.iter().rposition(|b| *b == b'\n') {
+            self.write_pending(newline + 1)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // A prompt without a newline; only complete UTF-8 sequences can be written out
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        self.write_pending(complete)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_cjk_punctuation() {
+        assert!("，。：“”…【】、（）".chars().all(is_cjk_punctuation));
+        assert!(!"钟Ａ１,.a".chars().any(is_cjk_punctuation));
+    }
+
+    #[test]
+    fn test_pinyin() {
+        assert_eq!(pinyin("钟表匠", PinyinStyle::Marks), "zhōng biǎo jiàng");
+        assert_eq!(pinyin("鐘錶", PinyinStyle::Marks), "zhōng biǎo");
+        assert_eq!(pinyin("绿色的", PinyinStyle::Numbers), "lv4 se4 de5");
+        assert_eq!(with_tone_mark("lve4"), "lüè");
+        assert_eq!(with_tone_mark("gui4"), "guì");
+        assert_eq!(with_tone_mark("liu2"), "liú");
+        assert_eq!(with_tone_mark("zou3"), "zǒu");
+    }
+
+    #[test]
+    fn test_script_conversion() {
+        assert_eq!(to_traditional("制造或修理钟表者"), "制造或修理鐘表者");
+        assert_eq!(to_simplified("計算機病毒"), "计算机病毒");
+        // Every traditional form converts back, not only the default one
+        assert_eq!(to_simplified("頭髮"), "头发");
+        assert_eq!(normalize_input(" 你們好 "), "你们好");
+    }
+
+    #[test]
+    fn test_display() {
+        let display = Display {
+            script: Some(Script::Traditional),
+            pinyin: Some(PinyinStyle::Marks),
+        };
+        assert_eq!(
+            display.apply("Translation: hello\n  int. 你好"),
+            "Translation: hello\n  int. 你好 (nǐ hǎo)"
+        );
+        assert_eq!(display.apply("n. 电脑"), "n. 電腦 (diàn nǎo)");
+
+        let mut output = Vec::new();
+        let mut writer = Writer::new(&mut output, display);
+        write!(writer, "[1/1] 钟").unwrap();
+        writeln!(writer, "表").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[1/1] 鐘表 (zhōng biǎo)\n"
+        );
+    }
+}
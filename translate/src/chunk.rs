@@ -0,0 +1,436 @@
+use crate::ai::AITranslationService;
+use crate::error::{Error, Result};
+use crate::placeholder::Protected;
+use crate::usage::Usage;
+use crate::verify::Verification;
+use futures::{StreamExt, TryStreamExt, stream};
+
+/// Default token budget of one AI request when translating long text.
+pub const DEFAULT_CHUNK_TOKENS: usize = 1500;
+/// Default number of chunks translated at the same time.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Default number of short texts, such as subtitle cues, sent in one request.
+pub const DEFAULT_BATCH_SIZE: usize = 20;
+/// How much of the neighbouring chunks is sent along as context, in characters.
+const CONTEXT_CHARS: usize = 200;
+/// How many of the preceding texts are sent along as context with a batch.
+const CONTEXT_ITEMS: usize = 3;
+
+/// A piece of the input and the whitespace that followed it in the original text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    pub separator: String,
+}
+
+/// A rough token count: a Chinese character is about one token, other text about
+/// four characters per token. Good enough to stay well inside a context window.
+pub fn estimate_tokens(text: &str) -> usize {
+    let han = text.chars().filter(|c| crate::chinese::is_han(*c)).count();
+    let other = text.chars().count() - han;
+    han + other.div_ceil(4)
+}
+
+/// Split `text` into chunks of at most `budget` estimated tokens, breaking at
+/// paragraph and sentence boundaries. Only a sentence longer than the budget on
+/// its own is cut in the middle.
+pub fn split(text: &str, budget: usize) -> Vec<Chunk> {
+    let budget = budget.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut separator = String::new();
+    for (sentence, following) in sentences(text) {
+        for (piece, piece_separator) in cut(sentence, following, budget) {
+            if !current.is_empty() && estimate_tokens(&current) + estimate_tokens(piece) > budget {
+                chunks.push(Chunk {
+                    text: std::mem::take(&mut current),
+                    separator: std::mem::take(&mut separator),
+                });
+            } else {
+                current.push_str(&separator);
+            }
+            current.push_str(piece);
+            separator = piece_separator.to_string();
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(Chunk {
+            text: current,
+            separator: String::new(),
+        });
+    }
+    chunks
+}
+
+/// Sentences of `text` with the whitespace after each. A line break always ends a sentence.
+fn sentences(text: &str) -> Vec<(&str, &str)> {
+    let mut sentences = Vec::new();
+    let mut start = text.len() - text.trim_start().len();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let mut end = match c {
+            '\n' => i,
+            '。' | '！' | '？' | '；' | '!' | '?' | '…' => i + c.len_utf8(),
+            // Not inside numbers such as 3.14, nor after abbreviations such as e.g. or Dr.
+            '.' if next.is_none_or(char::is_whitespace) && !is_abbreviation(&text[..i]) => i + 1,
+            ';' if next.is_none_or(char::is_whitespace) => i + 1,
+            _ => continue,
+        };
+        // Closing quotes and brackets belong to the sentence they close
+        while c != '\n'
+            && let Some(&(j, close)) = chars.peek()
+            && matches!(close, '”' | '’' | '」' | '』' | '）' | ')' | '"' | '\'')
+        {
+            end = j + close.len_utf8();
+            chars.next();
+        }
+        let mut resume = if c == '\n' { i + 1 } else { end };
+        while let Some(&(j, space)) = chars.peek()
+            && space.is_whitespace()
+        {
+            resume = j + space.len_utf8();
+            chars.next();
+        }
+        let sentence = text[start..end].trim_end();
+        if !sentence.is_empty() {
+            sentences.push((sentence, &text[start + sentence.len()..resume]));
+        }
+        start = resume;
+    }
+    let rest = text[start..].trim_end();
+    if !rest.is_empty() {
+        sentences.push((rest, ""));
+    }
+    sentences
+}
+
+/// Cut a sentence that doesn't fit the budget into pieces that do.
+fn cut<'a>(sentence: &'a str, following: &'a str, budget: usize) -> Vec<(&'a str, &'a str)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0.0;
+    for (i, c) in sentence.char_indices() {
+        let cost = if crate::chinese::is_han(c) { 1.0 } else { 0.25 };
+        if tokens + cost > budget as f32 && i > start {
+            pieces.push((&sentence[start..i], ""));
+            start = i;
+            tokens = 0.0;
+        }
+        tokens += cost;
+    }
+    pieces.push((&sentence[start..], following));
+    pieces
+}
+
+/// A long text translated chunk by chunk.
+#[derive(Debug)]
+pub struct Passage {
+    pub translation: String,
+    /// Token usage of every request, including retries and back-translations
+    pub usage: Option<Usage>,
+    /// A chunk lost some of its markers even when it was translated again
+    pub lost_markers: bool,
+    /// With verification, the back-translations of the chunks and the lowest similarity
+    /// of any chunk, so a single drifted chunk is flagged
+    pub verification: Option<Verification>,
+}
+
+struct TranslatedChunk {
+    translation: String,
+    usage: Option<Usage>,
+    kept_markers: bool,
+    verification: Option<Verification>,
+}
+
+/// Translate long protected text chunk by chunk, at most `concurrency` requests at a
+/// time, and join the translations in the original order. Each request also gets the
+/// end of the previous chunk and the start of the next one so terms stay consistent.
+/// A chunk that loses markers is translated once more, and with `verify_threshold`
+/// each chunk is back-translated and checked on its own.
+pub async fn translate(
+    service: &AITranslationService,
+    protected: &Protected,
+    budget: usize,
+    concurrency: usize,
+    verify_threshold: Option<f32>,
+) -> Result<Passage> {
+    let chunks = split(&protected.text, budget);
+    let requests = chunks.iter().enumerate().map(|(i, chunk)| {
+        let before = i
+            .checked_sub(1)
+            .map(|previous| tail(&chunks[previous].text, CONTEXT_CHARS))
+            .unwrap_or_default();
+        let after = chunks
+            .get(i + 1)
+            .map(|next| head(&next.text, CONTEXT_CHARS))
+            .unwrap_or_default();
+        async move {
+            let mut total = None;
+            let (mut translation, usage) = service
+                .translate_passage(&chunk.text, before, after)
+                .await?;
+            add_usage(&mut total, usage);
+            if !protected.keeps_markers(&chunk.text, &translation) {
+                eprintln!("Warning: a chunk lost some code, URLs or words; retrying");
+                let (retried, usage) = service
+                    .translate_passage(&chunk.text, before, after)
+                    .await?;
+                add_usage(&mut total, usage);
+                translation = retried;
+            }
+            let kept_markers = protected.keeps_markers(&chunk.text, &translation);
+            let translation = protected.restore_lossy(&translation);
+
+            let verification = match verify_threshold {
+                Some(threshold) => {
+                    let (back_translation, usage) = service.back_translate(&translation).await?;
+                    add_usage(&mut total, usage);
+                    let original = protected.restore_lossy(&chunk.text);
+                    Some(Verification::new(&original, &back_translation, threshold))
+                }
+                None => None,
+            };
+            Ok::<_, Error>(TranslatedChunk {
+                translation,
+                usage: total,
+                kept_markers,
+                verification,
+            })
+        }
+    });
+    let results: Vec<TranslatedChunk> = stream::iter(requests)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    let mut translation = String::new();
+    let mut usage = None;
+    let mut back_translations = Vec::new();
+    let mut lowest: Option<Verification> = None;
+    for (chunk, result) in chunks.iter().zip(&results) {
+        translation.push_str(&result.translation);
+        translation.push_str(&join_separator(&chunk.separator));
+        add_usage(&mut usage, result.usage);
+        if let Some(verification) = &result.verification {
+            back_translations.push(verification.back_translation.as_str());
+            if lowest
+                .as_ref()
+                .is_none_or(|lowest| verification.similarity < lowest.similarity)
+            {
+                lowest = Some(verification.clone());
+            }
+        }
+    }
+    let verification = lowest.map(|lowest| Verification {
+        back_translation: back_translations.join(" "),
+        ..lowest
+    });
+    Ok(Passage {
+        translation: translation.trim_end().to_string(),
+        usage,
+        lost_markers: results.iter().any(|result| !result.kept_markers),
+        verification,
+    })
+}
+
+fn add_usage(total: &mut Option<Usage>, usage: Option<Usage>) {
+    if let Some(usage) = usage {
+        let total = total.get_or_insert_with(Usage::default);
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+    }
+}
+
+/// Translate many short texts in batches of `batch_size`, at most `concurrency` batches
+/// at a time, each with the texts just before it as context. The translations are in
+/// the order of `items`. A batch whose reply doesn't have one translation per text is
+/// translated again one text at a time.
+pub async fn translate_batches(
+    service: &AITranslationService,
+    items: &[String],
+    batch_size: usize,
+    concurrency: usize,
+) -> Result<Vec<String>> {
+    let batch_size = batch_size.max(1);
+    let requests = items.chunks(batch_size).enumerate().map(|(n, batch)| {
+        let start = n * batch_size;
+        let context = &items[start.saturating_sub(CONTEXT_ITEMS)..start];
+        async move {
+            match service.translate_lines(batch, context).await {
+                Ok((translations, _)) => Ok(translations),
+                Err(Error::Parse(_)) => {
+                    let mut translations = Vec::new();
+                    for (i, item) in batch.iter().enumerate() {
+                        let before = if i > 0 {
+                            &batch[i - 1]
+                        } else {
+                            context.last().map_or("", String::as_str)
+                        };
+                        let (translation, _) = service.translate_passage(item, before, "").await?;
+                        translations.push(translation);
+                    }
+                    Ok(translations)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    });
+    let batches: Vec<Vec<String>> = stream::iter(requests)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    Ok(batches.into_iter().flatten().collect())
+}
+
+/// Keep paragraph breaks; anything else becomes the space English needs between sentences.
+fn join_separator(separator: &str) -> String {
+    let newlines: String = separator.chars().filter(|c| *c == '\n').collect();
+    if newlines.is_empty() {
+        " ".to_string()
+    } else {
+        newlines
+    }
+}
+
+fn head(text: &str, chars: usize) -> &str {
+    match text.char_indices().nth(chars) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
+}
+
+fn tail(text: &str, chars: usize) -> &str {
+    let count = text.chars().count();
+    match text.char_indices().nth(count.saturating_sub(chars)) {
+        Some((i, _)) => &text[i..],
+        None => "",
+    }
+}
+
+/// Titles that are followed by a name rather than the end of a sentence.
+const TITLES: [&str; 6] = ["Mr", "Mrs", "Ms", "Dr", "Prof", "vs"];
+
+/// Whether the word before a `.` is an abbreviation: single letters joined by dots,
+/// as in e.g. or U.S., or a title such as Dr.
+fn is_abbreviation(before: &str) -> bool {
+    let word = before
+        .rsplit(|c: char| c.is_whitespace() || c == '(')
+        .next()
+        .unwrap_or_default();
+    TITLES.contains(&word)
+        || (word.contains('.')
+            && word
+                .split('.')
+                .all(|part| part.len() == 1 && part.chars().all(|c| c.is_ascii_alphabetic())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::placeholder;
+    use crate::testing::{MockServer, chat_reply};
+
+    #[test]
+    fn test_split() {
+        let text = "第一句话。第二句话！\n\n第三段。It costs 3.14 dollars. Clocks, e.g. this one, run (i.e. tick). Ask Dr. Li. Done";
+        let sentences: Vec<&str> = sentences(text).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(
+            sentences,
+            vec![
+                "第一句话。",
+                "第二句话！",
+                "第三段。",
+                "It costs 3.14 dollars.",
+                "Clocks, e.g. this one, run (i.e. tick).",
+                "Ask Dr. Li.",
+                "Done"
+            ]
+        );
+
+        let chunks = split(text, 10);
+        assert_eq!(chunks[0].text, "第一句话。第二句话！");
+        assert_eq!(chunks[0].separator, "\n\n");
+        assert!(chunks.iter().all(|c| estimate_tokens(&c.text) <= 10));
+
+        // A sentence longer than the budget is cut in the middle
+        let chunks = split("这是一个很长很长的句子。", 5);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<String>(),
+            "这是一个很长很长的句子。"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_translate_batches() {
+        let items: Vec<String> = ["一", "二", "三"].iter().map(|s| s.to_string()).collect();
+        let server = MockServer::start(vec![
+            chat_reply(r#"{"translations": ["one", "two"]}"#),
+            // The wrong number of translations falls back to one request per text
+            chat_reply(r#"{"translations": []}"#),
+            chat_reply("three"),
+        ])
+        .await;
+        let translations = translate_batches(&server.service(), &items, 2, 1)
+            .await
+            .unwrap();
+        assert_eq!(translations, vec!["one", "two", "three"]);
+
+        let requests = server.requests();
+        let input: serde_json::Value =
+            serde_json::from_str(requests[1]["messages"][1]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(input["items"], serde_json::json!(["三"]));
+        assert_eq!(input["context"], serde_json::json!(["一", "二"]));
+    }
+
+    #[tokio::test]
+    async fn test_translate_in_order() {
+        let server = MockServer::start(vec![
+            chat_reply("First sentence."),
+            chat_reply("Second sentence."),
+            chat_reply("Third paragraph."),
+        ])
+        .await;
+        let protected = placeholder::protect_mixed("第一句话。第二句话。\n第三段。");
+        let passage = translate(&server.service(), &protected, 5, 1, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            passage.translation,
+            "First sentence. Second sentence.\nThird paragraph."
+        );
+        assert!(!passage.lost_markers && passage.verification.is_none());
+
+        let requests = server.requests();
+        let second = requests[1]["messages"][1]["content"].as_str().unwrap();
+        assert!(second.contains("<translate>第二句话。</translate>"));
+        assert!(second.contains("<context>第一句话。</context>"));
+        assert!(second.contains("<context>第三段。</context>"));
+    }
+
+    #[tokio::test]
+    async fn test_translate_retries_and_verifies_chunks() {
+        let server = MockServer::start(vec![
+            chat_reply("First sentence."),
+            chat_reply("完全不同"),
+            chat_reply("List them."),
+            chat_reply("List them with ⟦0⟧."),
+            chat_reply("用 ls 列出"),
+        ])
+        .await;
+        let protected = placeholder::protect_mixed("第一句话。用 `ls` 列出。");
+        let passage = translate(&server.service(), &protected, 5, 1, Some(0.5))
+            .await
+            .unwrap();
+        // The second chunk lost its marker, so only that one was sent again
+        assert_eq!(passage.translation, "First sentence. List them with `ls`.");
+        assert!(!passage.lost_markers);
+        assert_eq!(server.requests().len(), 5);
+
+        // The first chunk drifted, which the whole passage reports
+        let verification = passage.verification.unwrap();
+        assert_eq!(verification.back_translation, "完全不同 用 ls 列出");
+        assert!(!verification.passed());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::ai::AITranslationService;
+use crate::chinese::is_han;
+use crate::error::Result;
+use crate::placeholder;
+use std::ops::Range;
+
+/// Git's scissors line; everything below it (e.g. the diff of `commit -v`) is left as it is.
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// A commit message split into the parts that are translated and the parts kept as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitMessage {
+    parts: Vec<Part>,
+    /// The scissors line and what follows it
+    tail: Option<String>,
+    trailing_newline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    /// The subject, without its Conventional Commits prefix such as `fix(parser)!: `
+    Subject { prefix: String, text: String },
+    /// A body paragraph
+    Paragraph(String),
+    /// Blank lines, `#` comments and the trailer block
+    Verbatim(String),
+}
+
+/// Parse a commit message as git writes it to `COMMIT_EDITMSG`.
+pub fn parse(message: &str) -> CommitMessage {
+    let (message, tail) = match message.find(SCISSORS) {
+        Some(i) => (&message[..i], Some(message[i..].to_string())),
+        None => (message, None),
+    };
+    let mut parts = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in message.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            if !paragraph.is_empty() {
+                parts.push(Part::Paragraph(paragraph.join("\n")));
+                paragraph.clear();
+            }
+            parts.push(Part::Verbatim(line.to_string()));
+        } else {
+            paragraph.push(line);
+        }
+    }
+    if !paragraph.is_empty() {
+        parts.push(Part::Paragraph(paragraph.join("\n")));
+    }
+
+    let paragraphs: Vec<usize> = (0..parts.len())
+        .filter(|&i| matches!(parts[i], Part::Paragraph(_)))
+        .collect();
+    if let Some(&last) = paragraphs.last()
+        && paragraphs.len() > 1
+        && let Part::Paragraph(text) = &parts[last]
+        && is_trailer_block(text)
+    {
+        parts[last] = Part::Verbatim(text.clone());
+    }
+    if let Some(&first) = paragraphs.first()
+        && let Part::Paragraph(text) = &parts[first]
+    {
+        let (subject, rest) = text.split_once('\n').unwrap_or((text, ""));
+        let prefix_len = conventional_prefix_len(subject);
+        let subject = Part::Subject {
+            prefix: subject[..prefix_len].to_string(),
+            text: subject[prefix_len..].to_string(),
+        };
+        // Lines right under the subject are unusual, but they're body text all the same
+        if rest.is_empty() {
+            parts[first] = subject;
+        } else {
+            let rest = Part::Paragraph(rest.to_string());
+            parts.splice(first..=first, [subject, rest]);
+        }
+    }
+
+    CommitMessage {
+        parts,
+        trailing_newline: message.ends_with('\n'),
+        tail,
+    }
+}
+
+impl CommitMessage {
+    fn texts_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.parts.iter_mut().filter_map(|part| match part {
+            Part::Subject { text, .. } | Part::Paragraph(text) => Some(text),
+            Part::Verbatim(_) => None,
+        })
+    }
+
+    pub fn has_chinese(&self) -> bool {
+        self.parts.iter().any(|part| match part {
+            Part::Subject { text, .. } | Part::Paragraph(text) => text.chars().any(is_han),
+            Part::Verbatim(_) => false,
+        })
+    }
+
+    /// Translate the subject and body paragraphs that contain Chinese, in one request.
+    /// Issue references and `code` are kept exactly; a paragraph whose translation lost
+    /// one of them stays in Chinese. Returns the number of paragraphs translated.
+    pub async fn translate(&mut self, service: &AITranslationService) -> Result<usize> {
+        let mut texts: Vec<(&mut String, placeholder::Protected)> = self
+            .texts_mut()
+            .filter(|text| text.chars().any(is_han))
+            .map(|text| {
+                let protected = placeholder::protect_spans(text, &protected_spans(text));
+                (text, protected)
+            })
+            .collect();
+        if texts.is_empty() {
+            return Ok(0);
+        }
+        let items: Vec<String> = texts.iter().map(|(_, p)| p.text.clone()).collect();
+        let translations = crate::chunk::translate_batches(service, &items, items.len(), 1).await?;
+
+        let mut translated = 0;
+        for ((text, protected), translation) in texts.iter_mut().zip(translations) {
+            match protected.restore(translation.trim()) {
+                Some(translation) => {
+                    **text = translation;
+                    translated += 1;
+                }
+                None => eprintln!(
+                    "Warning: the translation of '{}' lost an issue reference or code, left as it is",
+                    text
+                ),
+            }
+        }
+        Ok(translated)
+    }
+}
+
+impl std::fmt::Display for CommitMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<String> = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Subject { prefix, text } => format!("{}{}", prefix, text),
+                Part::Paragraph(text) | Part::Verbatim(text) => text.clone(),
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))?;
+        if self.trailing_newline {
+            writeln!(f)?;
+        }
+        if let Some(tail) = &self.tail {
+            write!(f, "{}", tail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of a Conventional Commits prefix such as `feat: ` or `fix(parser)!: `.
+fn conventional_prefix_len(subject: &str) -> usize {
+    let kind = subject.bytes().take_while(u8::is_ascii_alphabetic).count();
+    if kind == 0 {
+        return 0;
+    }
+    let mut i = kind;
+    if subject[i..].starts_with('(') {
+        match subject[i..].find(')') {
+            Some(close) => i += close + 1,
+            None => return 0,
+        }
+    }
+    if subject[i..].starts_with('!') {
+        i += 1;
+    }
+    match subject[i..].strip_prefix(':') {
+        Some(rest) => subject.len() - rest.trim_start().len(),
+        None => 0,
+    }
+}
+
+/// Whether every line of a paragraph is a git trailer (`Signed-off-by: ...`), a
+/// Conventional Commits footer (`BREAKING CHANGE: ...`, `Refs #12`) or the
+/// indented continuation of one.
+fn is_trailer_block(paragraph: &str) -> bool {
+    paragraph.lines().enumerate().all(|(i, line)| {
+        if i > 0 && line.starts_with(char::is_whitespace) {
+            return true;
+        }
+        let token = line
+            .bytes()
+            .take_while(|b| b.is_ascii_alphanumeric() || *b == b'-')
+            .count();
+        let rest = &line[token..];
+        (token > 0 && (rest.starts_with(": ") || rest.starts_with(" #")))
+            || line.starts_with("BREAKING CHANGE: ")
+    })
+}
+
+/// Issue references (`#12`, `!34`, `owner/repo#56`, `GH-78`, `PROJ-90`) and `code`
+/// spans, which the translation has to keep exactly.
+fn protected_spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let at_boundary =
+            !text[..start].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '/');
+        let len = if text[start..].starts_with('`') {
+            text[start + 1..].find('`').map(|end| end + 2)
+        } else if at_boundary {
+            issue_ref_len(&text[start..])
+        } else {
+            None
+        };
+        match len {
+            Some(len) => {
+                spans.push(start..start + len);
+                start += len;
+            }
+            None => start += text[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    spans
+}
+
+fn issue_ref_len(text: &str) -> Option<usize> {
+    let name_len = |s: &str| {
+        s.bytes()
+            .take_while(|b| b.is_ascii_alphanumeric() || b"_.-".contains(b))
+            .count()
+    };
+    let digits_len = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+
+    let mut i = 0;
+    let owner = name_len(text);
+    if owner > 0 && text[owner..].starts_with('/') {
+        let repo = name_len(&text[owner + 1..]);
+        if repo > 0 && text[owner + 1 + repo..].starts_with('#') {
+            i = owner + 1 + repo;
+        }
+    }
+    let len = if text[i..].starts_with(['#', '!']) {
+        let digits = digits_len(&text[i + 1..]);
+        (digits > 0).then_some(i + 1 + digits)?
+    } else {
+        // A tracker key such as GH-78 or PROJ-90
+        let key = text
+            .bytes()
+            .take_while(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+            .count();
+        if key == 0 || !text.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return None;
+        }
+        let digits = text[key..].strip_prefix('-').map_or(0, digits_len);
+        (digits > 0).then_some(key + 1 + digits)?
+    };
+    let at_end = !text[len..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    at_end.then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, chat_reply};
+
+    const MESSAGE: &str = "fix(parser)!: 修复 #12 中的崩溃\n\n当输入为空时 `parse` 会 panic，见 GH-34。\n\nRefs: owner/repo#56\nSigned-off-by: 张三 <zhang@example.com>\n# Please enter the commit message for your changes.\n";
+
+    #[test]
+    fn test_parse() {
+        let message = parse(MESSAGE);
+        assert_eq!(message.to_string(), MESSAGE);
+        assert_eq!(
+            message.parts[0],
+            Part::Subject {
+                prefix: "fix(parser)!: ".to_string(),
+                text: "修复 #12 中的崩溃".to_string()
+            }
+        );
+        assert!(matches!(&message.parts[4], Part::Verbatim(text) if text.starts_with("Refs")));
+
+        let text = "见 #12、!3 和 owner/repo#56，不是 C#1 或 abc-1";
+        let spans: Vec<&str> = protected_spans(text)
+            .into_iter()
+            .map(|span| &text[span])
+            .collect();
+        assert_eq!(spans, vec!["#12", "!3", "owner/repo#56"]);
+        assert_eq!(conventional_prefix_len("修复: 崩溃"), 0);
+        assert_eq!(conventional_prefix_len("docs: 更新"), 6);
+    }
+
+    #[tokio::test]
+    async fn test_translate() {
+        let server = MockServer::start(vec![chat_reply(
+            r#"{"translations": ["fix the crash in ⟦0⟧", "⟦0⟧ panics on empty input, see ⟦1⟧."]}"#,
+        )])
+        .await;
+        let mut message = parse(MESSAGE);
+        assert_eq!(message.translate(&server.service()).await.unwrap(), 2);
+        assert_eq!(
+            message.to_string(),
+            "fix(parser)!: fix the crash in #12\n\n`parse` panics on empty input, see GH-34.\n\nRefs: owner/repo#56\nSigned-off-by: 张三 <zhang@example.com>\n# Please enter the commit message for your changes.\n"
+        );
+
+        let input: serde_json::Value = serde_json::from_str(
+            server.requests()[0]["messages"][1]["content"]
+                .as_str()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(input["items"][0], "修复 ⟦0⟧ 中的崩溃");
+
+        // Chinese in comments and trailers alone isn't translated
+        let message =
+            parse("chore: bump version\n\nSigned-off-by: 张三 <zhang@example.com>\n# 注释\n");
+        assert!(!message.has_chinese());
+    }
+}
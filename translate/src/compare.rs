@@ -0,0 +1,234 @@
+use crate::ai::AITranslationService;
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use crate::placeholder::{self, Protected};
+use futures::future::join_all;
+use serde::Serialize;
+use std::time::Instant;
+
+/// The answer of one model in a comparison run.
+#[derive(Debug, Serialize)]
+pub struct ModelResult {
+    pub model: String,
+    pub latency_ms: u128,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<Entry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A full comparison run, serialized as one record of an evaluation set.
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub text: String,
+    pub results: Vec<ModelResult>,
+}
+
+/// Send `text` to every model concurrently and collect the answers in the order given.
+/// When no model answers, the error of the first one is returned with the comparison.
+pub async fn compare(text: &str, models: &[String]) -> (Comparison, Option<Error>) {
+    let services = models
+        .iter()
+        .map(|name| (name.as_str(), AITranslationService::for_model(name)))
+        .collect();
+    compare_services(text, services).await
+}
+
+async fn compare_services(
+    text: &str,
+    services: Vec<(&str, Result<AITranslationService>)>,
+) -> (Comparison, Option<Error>) {
+    // The same markers as the `--ai` path, so every model sees what a translation sends
+    let protected = placeholder::protect_mixed(text);
+    let outcomes = join_all(
+        services
+            .into_iter()
+            .map(|(name, service)| run_model(&protected, name, service)),
+    )
+    .await;
+
+    let failed = outcomes.iter().all(|(result, _)| result.entry.is_none());
+    let mut results = Vec::new();
+    let mut first_error = None;
+    for (result, error) in outcomes {
+        results.push(result);
+        if failed && first_error.is_none() {
+            first_error = error;
+        }
+    }
+    let comparison = Comparison {
+        text: text.to_string(),
+        results,
+    };
+    (comparison, first_error)
+}
+
+async fn run_model(
+    protected: &Protected,
+    name: &str,
+    service: Result<AITranslationService>,
+) -> (ModelResult, Option<Error>) {
+    let start = Instant::now();
+    let outcome = match service {
+        Ok(service) => service.translate(&protected.text).await,
+        Err(e) => Err(e),
+    };
+    let latency_ms = start.elapsed().as_millis();
+
+    match outcome {
+        Ok((mut entry, usage)) => {
+            entry.headword = protected.restore_lossy(&entry.headword);
+            protected.restore_entry(&mut entry);
+            let result = ModelResult {
+                model: name.to_string(),
+                latency_ms,
+                prompt_tokens: usage.map(|u| u.prompt_tokens),
+                completion_tokens: usage.map(|u| u.completion_tokens),
+                entry: Some(entry),
+                error: None,
+            };
+            (result, None)
+        }
+        Err(e) => {
+            let result = ModelResult {
+                model: name.to_string(),
+                latency_ms,
+                prompt_tokens: None,
+                completion_tokens: None,
+                entry: None,
+                error: Some(e.to_string()),
+            };
+            (result, Some(e))
+        }
+    }
+}
+
+/// Render the answers as a table, one row per model, followed by each model's full entry.
+pub fn render(comparison: &Comparison) -> String {
+    let width = comparison
+        .results
+        .iter()
+        .map(|r| r.model.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Model".len());
+
+    let mut lines = vec![format!(
+        "{:<width$}  {:>9}  {:>7}  Translation",
+        "Model", "Latency", "Tokens"
+    )];
+    for result in &comparison.results {
+        let tokens = match (result.prompt_tokens, result.completion_tokens) {
+            (Some(p), Some(c)) => (p + c).to_string(),
+            _ => "-".to_string(),
+        };
+        let answer = match (&result.entry, &result.error) {
+            (Some(entry), _) => entry.headword.clone(),
+            (None, Some(error)) => format!("error: {}", error),
+            (None, None) => String::new(),
+        };
+        lines.push(format!(
+            "{:<width$}  {:>8.2}s  {:>7}  {}",
+            result.model,
+            result.latency_ms as f64 / 1000.0,
+            tokens,
+            answer
+        ));
+    }
+
+    for result in &comparison.results {
+        if let Some(entry) = &result.entry {
+            lines.push(String::new());
+            lines.push(format!("[{}]", result.model));
+            lines.push(entry.to_string());
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, chat_reply};
+
+    #[tokio::test]
+    async fn test_compare() {
+        let text = "把 `HashMap` 清空";
+        let answering =
+            MockServer::start(vec![chat_reply(r#"{"translation": "Clear ⟦0⟧"}"#)]).await;
+        let failing = MockServer::start(vec![(503, "overloaded".to_string())]).await;
+        let (comparison, error) = compare_services(
+            text,
+            vec![
+                ("good", Ok(answering.service())),
+                ("bad", Ok(failing.service())),
+            ],
+        )
+        .await;
+        assert!(error.is_none());
+        assert_eq!(
+            answering.requests()[0]["messages"][1]["content"],
+            "把 ⟦0⟧ 清空"
+        );
+        let entry = comparison.results[0].entry.as_ref().unwrap();
+        assert_eq!(entry.headword, "Clear `HashMap`");
+        assert!(comparison.results[1].error.is_some());
+
+        // Without a single answer, the first model's error is reported
+        let failing = MockServer::start(vec![(503, "overloaded".to_string())]).await;
+        let (comparison, error) = compare_services(
+            text,
+            vec![
+                ("unset", Err(Error::Config("no key".to_string()))),
+                ("bad", Ok(failing.service())),
+            ],
+        )
+        .await;
+        assert_eq!(comparison.results.len(), 2);
+        assert!(matches!(error, Some(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_render_comparison() {
+        let comparison = Comparison {
+            text: "钟表匠".to_string(),
+            results: vec![
+                ModelResult {
+                    model: "glm-4.5".to_string(),
+                    latency_ms: 1250,
+                    prompt_tokens: Some(80),
+                    completion_tokens: Some(20),
+                    entry: Some(Entry {
+                        headword: "clockmaker".to_string(),
+                        ..Default::default()
+                    }),
+                    error: None,
+                },
+                ModelResult {
+                    model: "deepseek-chat".to_string(),
+                    latency_ms: 300,
+                    prompt_tokens: None,
+                    completion_tokens: None,
+                    entry: None,
+                    error: Some("timeout".to_string()),
+                },
+            ],
+        };
+
+        let output = render(&comparison);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[1], "glm-4.5            1.25s      100  clockmaker");
+        assert_eq!(
+            lines[2],
+            "deepseek-chat      0.30s        -  error: timeout"
+        );
+        assert!(output.contains("[glm-4.5]\nTranslation: clockmaker"));
+
+        let json = serde_json::to_value(&comparison).unwrap();
+        assert_eq!(json["results"][0]["entry"]["headword"], "clockmaker");
+        assert_eq!(json["results"][1]["error"], "timeout");
+    }
+}
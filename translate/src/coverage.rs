@@ -0,0 +1,247 @@
+use crate::chinese::is_han;
+use crate::dictionary::{Dictionary, DomainFilter};
+use crate::entry::split_part_of_speech;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Function words that are neither glossary terms nor worth adding as one.
+const STOP_CHARACTERS: &str = "的了是在和与及等把被对也都就而或这那个一不有为以从到将之其并且于";
+
+/// Length range of the candidate entries suggested from the unknown text, in characters.
+const CANDIDATE_LENGTHS: std::ops::RangeInclusive<usize> = 2..=4;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Term {
+    pub term: String,
+    pub count: usize,
+}
+
+/// How much of a document the dictionaries cover.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Coverage {
+    /// Words found in the dictionaries plus runs of unknown text, function words aside
+    pub tokens: usize,
+    pub known: usize,
+    pub unknown: usize,
+    /// `known / tokens`, 1.0 for a document without Chinese
+    pub coverage: f32,
+    /// The share of Chinese characters inside known words
+    pub character_coverage: f32,
+    /// The runs of unknown text, most frequent first
+    pub unknown_terms: Vec<Term>,
+    /// Short strings that recur in the unknown text, likely terms to add to a dictionary
+    pub candidates: Vec<Term>,
+}
+
+/// The Chinese words of the dictionaries: each part of a gloss between punctuation,
+/// e.g. 时钟 and 钟 for "n. 时钟；钟".
+pub fn vocabulary(dictionary: &Dictionary, filter: &DomainFilter) -> HashSet<String> {
+    dictionary
+        .iter()
+        .filter(|(headword, _)| {
+            !filter.restrict
+                || dictionary.entry(headword).is_some_and(|found| {
+                    found.source.domains.iter().any(|t| filter.tags.contains(t))
+                })
+        })
+        .flat_map(|(_, gloss)| {
+            split_part_of_speech(gloss)
+                .1
+                .split(|c: char| !is_han(c))
+                .filter(|word| !word.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Segment the Chinese of `text` by forward maximum matching against `vocabulary` and
+/// report the coverage, with at most `top` unknown terms and candidates.
+pub fn report(text: &str, vocabulary: &HashSet<String>, top: usize) -> Coverage {
+    let longest = vocabulary
+        .iter()
+        .map(|word| word.chars().count())
+        .max()
+        .unwrap_or(1);
+    let mut known = 0;
+    let mut known_chars = 0;
+    let mut total_chars = 0;
+    let mut unknown_runs: Vec<String> = Vec::new();
+
+    for run in text
+        .split(|c: char| !is_han(c))
+        .filter(|run| !run.is_empty())
+    {
+        let chars: Vec<char> = run.chars().collect();
+        total_chars += chars.len();
+        let mut unknown = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let matched = (1..=longest.min(chars.len() - i))
+                .rev()
+                .find(|&len| vocabulary.contains(&chars[i..i + len].iter().collect::<String>()));
+            match matched {
+                Some(len) => {
+                    known += 1;
+                    known_chars += len;
+                    unknown_runs.extend(take_run(&mut unknown));
+                    i += len;
+                }
+                None if STOP_CHARACTERS.contains(chars[i]) => {
+                    unknown_runs.extend(take_run(&mut unknown));
+                    i += 1;
+                }
+                None => {
+                    unknown.push(chars[i]);
+                    i += 1;
+                }
+            }
+        }
+        unknown_runs.extend(take_run(&mut unknown));
+    }
+
+    let tokens = known + unknown_runs.len();
+    let ratio = |part: usize, whole: usize| {
+        if whole == 0 {
+            1.0
+        } else {
+            part as f32 / whole as f32
+        }
+    };
+    let mut run_counts: HashMap<&str, usize> = HashMap::new();
+    for run in &unknown_runs {
+        *run_counts.entry(run).or_insert(0) += 1;
+    }
+    Coverage {
+        tokens,
+        known,
+        unknown: unknown_runs.len(),
+        coverage: ratio(known, tokens),
+        character_coverage: ratio(known_chars, total_chars),
+        unknown_terms: ranked(run_counts, top, 1),
+        candidates: candidates(&unknown_runs, top),
+    }
+}
+
+fn take_run(unknown: &mut String) -> Option<String> {
+    (!unknown.is_empty()).then(|| std::mem::take(unknown))
+}
+
+/// Substrings that occur at least twice in the unknown runs. A substring is left out
+/// when a longer one containing it occurs as often, so 检测工具 hides 测工 and 工具.
+fn candidates(unknown_runs: &[String], top: usize) -> Vec<Term> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for run in unknown_runs {
+        let chars: Vec<char> = run.chars().collect();
+        for len in CANDIDATE_LENGTHS {
+            for gram in chars.windows(len) {
+                *counts.entry(gram.iter().collect()).or_insert(0) += 1;
+            }
+        }
+    }
+    // A longer string never occurs more often than the strings inside it, so it's enough
+    // to compare each one with its extensions by a character on either side
+    let mut hidden: HashSet<String> = HashSet::new();
+    for run in unknown_runs {
+        let chars: Vec<char> = run.chars().collect();
+        for len in CANDIDATE_LENGTHS.skip(1) {
+            for longer in chars.windows(len) {
+                let count = counts[&longer.iter().collect::<String>()];
+                for shorter in [&longer[1..], &longer[..len - 1]] {
+                    let shorter: String = shorter.iter().collect();
+                    if counts[&shorter] == count {
+                        hidden.insert(shorter);
+                    }
+                }
+            }
+        }
+    }
+    let closed: HashMap<&str, usize> = counts
+        .iter()
+        .filter(|(gram, _)| !hidden.contains(*gram))
+        .map(|(gram, count)| (gram.as_str(), *count))
+        .collect();
+    ranked(closed, top, 2)
+}
+
+/// Terms seen at least `min_count` times, most frequent first, then longest.
+fn ranked(counts: HashMap<&str, usize>, top: usize, min_count: usize) -> Vec<Term> {
+    let mut terms: Vec<Term> = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= min_count)
+        .map(|(term, count)| Term {
+            term: term.to_string(),
+            count,
+        })
+        .collect();
+    terms.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(b.term.chars().count().cmp(&a.term.chars().count()))
+            .then(a.term.cmp(&b.term))
+    });
+    terms.truncate(top);
+    terms
+}
+
+impl std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Coverage: {:.1}% of {} tokens known ({} known, {} unknown), {:.1}% of characters",
+            self.coverage * 100.0,
+            self.tokens,
+            self.known,
+            self.unknown,
+            self.character_coverage * 100.0
+        )?;
+        for (title, terms) in [
+            ("Most frequent unknown terms", &self.unknown_terms),
+            ("Candidate entries", &self.candidates),
+        ] {
+            if terms.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n{}:", title)?;
+            for term in terms {
+                writeln!(f, "  {}\t{}", term.term, term.count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_dictionary;
+
+    #[test]
+    fn test_report() {
+        let vocabulary = vocabulary(&fixture_dictionary(), &DomainFilter::default());
+        assert!(vocabulary.contains("时钟") && vocabulary.contains("钟"));
+
+        let text = "# 说明\n\n电脑病毒是恶意软件。检测病毒需要检测工具，检测工具很重要。";
+        let coverage = report(text, &vocabulary, 10);
+        // 电脑 病毒 恶意软件 病毒 against 说明 检测 需要检测工具 检测工具很重要
+        assert_eq!((coverage.known, coverage.unknown), (4, 4));
+        assert_eq!(coverage.coverage, 0.5);
+        assert_eq!(coverage.unknown_terms.len(), 4);
+        assert_eq!(
+            coverage.candidates,
+            vec![
+                Term {
+                    term: "检测".to_string(),
+                    count: 3
+                },
+                Term {
+                    term: "检测工具".to_string(),
+                    count: 2
+                },
+            ]
+        );
+
+        let empty = report("no Chinese", &vocabulary, 10);
+        assert_eq!((empty.tokens, empty.coverage), (0, 1.0));
+    }
+}
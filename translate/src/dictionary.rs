@@ -0,0 +1,459 @@
+use crate::entry::split_part_of_speech;
+use crate::error::{Error, Result};
+use crate::frequency::Frequencies;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Name of the optional manifest in the dictionary directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Settings for one dictionary file, as given in the manifest, e.g.
+/// `{"team.json": {"priority": 100, "domains": ["team"]}}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ManifestEntry {
+    priority: i32,
+    domains: Vec<String>,
+}
+
+/// A dictionary file that contributed entries.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub priority: i32,
+    pub domains: Vec<String>,
+}
+
+/// One definition of a headword, from one source.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub gloss: String,
+    pub source: usize,
+}
+
+/// Restricts or boosts lookups by domain tag.
+#[derive(Debug, Clone, Default)]
+pub struct DomainFilter {
+    pub tags: Vec<String>,
+    /// Only consider dictionaries tagged with one of `tags`, instead of ranking them first
+    pub restrict: bool,
+}
+
+/// A headword whose gloss contains the looked-up text.
+#[derive(Debug, Clone)]
+pub struct Match<'a> {
+    pub headword: &'a str,
+    pub gloss: &'a str,
+    pub source: &'a Source,
+}
+
+/// A headword defined differently by more than one dictionary.
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    pub headword: &'a str,
+    /// All definitions, the one that wins first
+    pub definitions: Vec<Match<'a>>,
+}
+
+/// The merged contents of every dictionary file in the dictionary directory.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    sources: Vec<Source>,
+    /// Definitions of each headword, ordered so the winning one comes first
+    entries: HashMap<String, Vec<Definition>>,
+    /// Manifest entries that name a file which doesn't exist
+    missing: Vec<String>,
+    /// Corpus word frequencies that rank headwords sharing a gloss
+    frequencies: Frequencies,
+}
+
+impl Dictionary {
+    /// Load every `.json` dictionary in `dir`, applying the manifest if there is one.
+    /// Higher priority wins on a duplicate headword; equal priorities are broken by file name.
+    /// `.tsv` files are word-frequency lists.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest: HashMap<String, ManifestEntry> = if manifest_path.is_file() {
+            let data = read(&manifest_path)?;
+            serde_json::from_str(&data).map_err(|e| {
+                Error::Dictionary(format!("Invalid {}: {}", manifest_path.display(), e))
+            })?
+        } else {
+            HashMap::new()
+        };
+
+        let mut files = Vec::new();
+        let mut frequency_lists = Vec::new();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| Error::Dictionary(format!("Cannot read {}: {}", dir.display(), e)))?;
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") if path.file_name().is_some_and(|name| name != MANIFEST_FILE) => {
+                    files.push(path)
+                }
+                Some("tsv") => frequency_lists.push(path),
+                _ => {}
+            }
+        }
+        files.sort();
+        frequency_lists.sort();
+
+        let mut dictionary = Dictionary::default();
+        for path in frequency_lists {
+            dictionary.frequencies.add_list(&read(&path)?);
+        }
+        for path in files {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let settings = manifest.get(&name).cloned().unwrap_or_default();
+            let data = read(&path)?;
+            let dict: HashMap<String, String> = serde_json::from_str(&data).map_err(|e| {
+                Error::Dictionary(format!("Invalid dictionary {}: {}", path.display(), e))
+            })?;
+            dictionary.add_source(
+                Source {
+                    name,
+                    priority: settings.priority,
+                    domains: settings.domains,
+                },
+                dict,
+            );
+        }
+
+        let mut missing: Vec<String> = manifest
+            .keys()
+            .filter(|name| !dir.join(name).is_file())
+            .cloned()
+            .collect();
+        missing.sort();
+        dictionary.missing = missing;
+
+        Ok(dictionary)
+    }
+
+    /// Merge one dictionary file. Sources must be added in file name order.
+    pub fn add_source(&mut self, source: Source, dict: HashMap<String, String>) {
+        let index = self.sources.len();
+        self.sources.push(source);
+        for (headword, gloss) in dict {
+            let definitions = self.entries.entry(headword).or_default();
+            definitions.push(Definition {
+                gloss,
+                source: index,
+            });
+            // Stable sort keeps file name order among equal priorities
+            definitions.sort_by_key(|d| -self.sources[d.source].priority);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    #[cfg(test)]
+    pub fn contains_key(&self, headword: &str) -> bool {
+        self.entries.contains_key(headword)
+    }
+
+    /// The winning gloss of a headword.
+    #[cfg(test)]
+    pub fn get(&self, headword: &str) -> Option<&String> {
+        self.entries
+            .get(headword)
+            .and_then(|definitions| definitions.first())
+            .map(|d| &d.gloss)
+    }
+
+    /// Every headword with its winning gloss, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().filter_map(|(headword, definitions)| {
+            definitions
+                .first()
+                .map(|d| (headword.as_str(), d.gloss.as_str()))
+        })
+    }
+
+    /// How often a headword occurs per million words, if the frequency lists have it.
+    pub fn frequency(&self, headword: &str) -> Option<f32> {
+        self.frequencies.get(headword)
+    }
+
+    /// Whether the frequency lists mark a headword as rare or archaic.
+    pub fn is_rare(&self, headword: &str) -> bool {
+        self.frequencies.is_rare(headword)
+    }
+
+    /// The winning definition of a headword and the dictionary it came from.
+    pub fn entry(&self, headword: &str) -> Option<Match<'_>> {
+        let (headword, definitions) = self.entries.get_key_value(headword)?;
+        let definition = definitions.first()?;
+        Some(Match {
+            headword,
+            gloss: &definition.gloss,
+            source: &self.sources[definition.source],
+        })
+    }
+
+    /// Find headwords whose gloss contains `text`, best match first.
+    ///
+    /// Each headword contributes its highest-ranked matching definition. Matches from
+    /// dictionaries tagged with a requested domain come first, then higher priorities,
+    /// then more frequent headwords.
+    pub fn lookup(&self, text: &str, filter: &DomainFilter) -> Vec<Match<'_>> {
+        self.lookup_by(filter, |gloss| gloss.contains(text))
+    }
+
+    /// Find headwords with a gloss segment equal to `text`, ranked like `lookup`. The
+    /// segments of "n. 时钟；钟" are 时钟 and 钟, so 时 doesn't match it.
+    pub fn lookup_exact(&self, text: &str, filter: &DomainFilter) -> Vec<Match<'_>> {
+        self.lookup_by(filter, |gloss| {
+            split_part_of_speech(gloss)
+                .1
+                .split(['；', ';'])
+                .any(|segment| segment.trim() == text)
+        })
+    }
+
+    fn lookup_by(&self, filter: &DomainFilter, matches: impl Fn(&str) -> bool) -> Vec<Match<'_>> {
+        let mut matches: Vec<(bool, Match)> = self
+            .entries
+            .iter()
+            .filter_map(|(headword, definitions)| {
+                definitions
+                    .iter()
+                    .filter(|d| matches(&d.gloss))
+                    .map(|d| {
+                        let source = &self.sources[d.source];
+                        let in_domain = source.domains.iter().any(|tag| filter.tags.contains(tag));
+                        (
+                            in_domain,
+                            Match {
+                                headword,
+                                gloss: &d.gloss,
+                                source,
+                            },
+                        )
+                    })
+                    .filter(|(in_domain, _)| *in_domain || !filter.restrict)
+                    // Definitions are already in priority order, so the first in-domain one is best
+                    .reduce(|best, next| if next.0 && !best.0 { next } else { best })
+            })
+            .collect();
+
+        matches.sort_by(|(a_domain, a), (b_domain, b)| {
+            b_domain
+                .cmp(a_domain)
+                .then(b.source.priority.cmp(&a.source.priority))
+                .then(self.frequency_order(a.headword, b.headword))
+                .then(a.headword.cmp(b.headword))
+        });
+        matches.into_iter().map(|(_, m)| m).collect()
+    }
+
+    /// More frequent headwords first; headwords missing from the lists last.
+    pub fn frequency_order(&self, a: &str, b: &str) -> std::cmp::Ordering {
+        let frequency = |headword| self.frequency(headword).unwrap_or(-1.0);
+        frequency(b).total_cmp(&frequency(a))
+    }
+
+    /// Headwords that more than one dictionary defines with different glosses.
+    pub fn conflicts(&self) -> Vec<Conflict<'_>> {
+        let mut conflicts: Vec<Conflict> = self
+            .entries
+            .iter()
+            .filter(|(_, definitions)| {
+                definitions
+                    .iter()
+                    .any(|d| d.gloss.trim() != definitions[0].gloss.trim())
+            })
+            .map(|(headword, definitions)| Conflict {
+                headword,
+                definitions: definitions
+                    .iter()
+                    .map(|d| Match {
+                        headword,
+                        gloss: &d.gloss,
+                        source: &self.sources[d.source],
+                    })
+                    .collect(),
+            })
+            .collect();
+        conflicts.sort_by(|a, b| a.headword.cmp(b.headword));
+        conflicts
+    }
+
+    /// Manifest entries that name a file which doesn't exist.
+    pub fn missing_sources(&self) -> &[String] {
+        &self.missing
+    }
+}
+
+/// Describe the dictionary for `translate validate`.
+pub fn validation_report(dictionary: &Dictionary) -> String {
+    let mut lines = vec![format!(
+        "Loaded {} dictionaries with {} headwords",
+        dictionary.sources().len(),
+        dictionary.len()
+    )];
+    for source in dictionary.sources() {
+        lines.push(format!("  {}", describe_source(source)));
+    }
+    if dictionary.frequencies.lists() > 0 {
+        lines.push(format!(
+            "Loaded {} frequency lists with {} words",
+            dictionary.frequencies.lists(),
+            dictionary.frequencies.len()
+        ));
+    }
+
+    for name in dictionary.missing_sources() {
+        lines.push(format!(
+            "Warning: {} lists {}, which does not exist",
+            MANIFEST_FILE, name
+        ));
+    }
+
+    let conflicts = dictionary.conflicts();
+    if conflicts.is_empty() {
+        lines.push("No conflicting definitions".to_string());
+    } else {
+        lines.push(format!("{} conflicting definitions:", conflicts.len()));
+        for conflict in &conflicts {
+            lines.push(format!("  {}", conflict.headword));
+            for (i, definition) in conflict.definitions.iter().enumerate() {
+                lines.push(format!(
+                    "    {} {}: {}",
+                    if i == 0 { "*" } else { " " },
+                    describe_source(definition.source),
+                    definition.gloss.replace('\n', " / ")
+                ));
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn describe_source(source: &Source) -> String {
+    if source.domains.is_empty() {
+        format!("{} (priority {})", source.name, source.priority)
+    } else {
+        format!(
+            "{} (priority {}, {})",
+            source.name,
+            source.priority,
+            source.domains.join(", ")
+        )
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| Error::Dictionary(format!("Cannot read {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str, priority: i32, domains: &[&str]) -> Source {
+        Source {
+            name: name.to_string(),
+            priority,
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn sample() -> Dictionary {
+        let mut dictionary = Dictionary::default();
+        dictionary.add_source(
+            source("base.json", 0, &[]),
+            dict(&[("virus", "n. 病毒"), ("bug", "n. 虫子")]),
+        );
+        dictionary.add_source(
+            source("computing.json", 0, &["computing"]),
+            dict(&[("virus", "n. 计算机病毒"), ("bug", "n. 程序错误；缺陷")]),
+        );
+        dictionary.add_source(
+            source("team.json", 10, &["team"]),
+            dict(&[("bug", "n. 缺陷")]),
+        );
+        dictionary
+    }
+
+    #[test]
+    fn test_priority_wins_on_duplicate_headword() {
+        let dictionary = sample();
+        assert_eq!(dictionary.get("bug"), Some(&"n. 缺陷".to_string()));
+        // Equal priorities fall back to file name order
+        assert_eq!(dictionary.get("virus"), Some(&"n. 病毒".to_string()));
+    }
+
+    #[test]
+    fn test_domain_boost_and_restrict() {
+        let dictionary = sample();
+        let none = DomainFilter::default();
+        assert_eq!(dictionary.lookup("病毒", &none)[0].gloss, "n. 病毒");
+
+        let boost = DomainFilter {
+            tags: vec!["computing".to_string()],
+            restrict: false,
+        };
+        assert_eq!(dictionary.lookup("病毒", &boost)[0].gloss, "n. 计算机病毒");
+        assert_eq!(dictionary.lookup("虫子", &boost).len(), 1);
+
+        let restrict = DomainFilter {
+            restrict: true,
+            ..boost
+        };
+        assert!(dictionary.lookup("虫子", &restrict).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_exact() {
+        let dictionary = sample();
+        let none = DomainFilter::default();
+        let headwords = |text| {
+            let mut found: Vec<&str> = dictionary
+                .lookup_exact(text, &none)
+                .iter()
+                .map(|m| m.headword)
+                .collect();
+            found.sort_unstable();
+            found
+        };
+        assert_eq!(headwords("病毒"), vec!["virus"]);
+        assert_eq!(headwords("程序错误"), vec!["bug"]);
+        assert!(headwords("病").is_empty());
+        assert_eq!(dictionary.lookup("病", &none).len(), 1);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let dictionary = sample();
+        let conflicts = dictionary.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].headword, "bug");
+        assert_eq!(conflicts[0].definitions[0].source.name, "team.json");
+        assert_eq!(conflicts[0].definitions.len(), 3);
+    }
+}
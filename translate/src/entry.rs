@@ -0,0 +1,188 @@
+use crate::memory::MemoryMatch;
+use crate::verify::Verification;
+use serde::Serialize;
+use std::fmt;
+
+/// A translation result, rendered the same way whether it came from the
+/// local dictionary or from the AI service.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Entry {
+    /// The primary English translation (the dictionary headword)
+    pub headword: String,
+    /// Part of speech, e.g. "n." or "v."
+    pub part_of_speech: Option<String>,
+    /// The Chinese gloss, without the part-of-speech prefix
+    pub gloss: Option<String>,
+    /// Other English words with the same meaning
+    pub alternatives: Vec<String>,
+    /// The headword and alternatives that the frequency lists mark as rare or archaic
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rare: Vec<String>,
+    /// An example sentence using the headword
+    pub example: Option<String>,
+    /// How confident the translator is, between 0.0 and 1.0
+    pub confidence: Option<f32>,
+    /// Back-translation check of an AI result, when `--verify` was used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    /// The translation memory segment this result was reused from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_match: Option<MemoryMatch>,
+}
+
+impl Entry {
+    /// Build an entry from a dictionary headword and its raw gloss
+    /// (e.g. "n. 制造或修理钟表者").
+    pub fn from_dictionary(headword: &str, raw_gloss: &str) -> Self {
+        let (part_of_speech, gloss) = split_part_of_speech(raw_gloss);
+        Self {
+            headword: headword.to_string(),
+            part_of_speech,
+            gloss: Some(gloss),
+            ..Default::default()
+        }
+    }
+}
+
+impl Entry {
+    fn mark_rare(&self, headword: &str) -> String {
+        if self.rare.iter().any(|rare| rare == headword) {
+            format!("{} (rare)", headword)
+        } else {
+            headword.to_string()
+        }
+    }
+}
+
+/// Split a leading part-of-speech marker such as "n." or "vt." off a gloss.
+pub fn split_part_of_speech(raw: &str) -> (Option<String>, String) {
+    let raw = raw.trim();
+    if let Some((first, rest)) = raw.split_once(char::is_whitespace) {
+        let is_marker = first.ends_with('.')
+            && first.len() > 1
+            && first[..first.len() - 1]
+                .chars()
+                .all(|c| c.is_ascii_alphabetic() || c == '&');
+        if is_marker {
+            return (Some(first.to_string()), rest.trim().to_string());
+        }
+    }
+    (None, raw.to_string())
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Translation: {}", self.mark_rare(&self.headword))?;
+
+        let gloss_line = match (&self.part_of_speech, &self.gloss) {
+            (Some(pos), Some(gloss)) => Some(format!("{} {}", pos, gloss)),
+            (Some(pos), None) => Some(pos.clone()),
+            (None, Some(gloss)) => Some(gloss.clone()),
+            (None, None) => None,
+        };
+        if let Some(line) = gloss_line {
+            for line in line.lines() {
+                write!(f, "\n  {}", line)?;
+            }
+        }
+
+        if !self.alternatives.is_empty() {
+            let alternatives: Vec<String> = self
+                .alternatives
+                .iter()
+                .map(|alternative| self.mark_rare(alternative))
+                .collect();
+            write!(f, "\nAlternatives: {}", alternatives.join(", "))?;
+        }
+        if let Some(example) = &self.example {
+            write!(f, "\nExample: {}", example)?;
+        }
+        if let Some(memory_match) = &self.memory_match {
+            if memory_match.score >= 1.0 {
+                write!(f, "\nMemory match: 100%")?;
+            } else {
+                write!(
+                    f,
+                    "\nMemory match: {:.0}% ({})",
+                    // Never round a fuzzy match up to 100%
+                    (memory_match.score * 100.0).min(99.0),
+                    memory_match.source
+                )?;
+            }
+        }
+        if let Some(verification) = &self.verification {
+            write!(
+                f,
+                "\nBack-translation: {} (similarity {:.0}%)",
+                verification.back_translation,
+                verification.similarity * 100.0
+            )?;
+            // A result that failed the check is flagged rather than shown as confident
+            if !verification.passed() {
+                return write!(
+                    f,
+                    "\nWarning: the back-translation differs from the original (below {:.0}%), the meaning may have drifted",
+                    verification.threshold * 100.0
+                );
+            }
+        }
+        if let Some(confidence) = self.confidence {
+            write!(f, "\nConfidence: {:.0}%", confidence * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_part_of_speech() {
+        assert_eq!(
+            split_part_of_speech("n. 制造或修理钟表者"),
+            (Some("n.".to_string()), "制造或修理钟表者".to_string())
+        );
+        assert_eq!(
+            split_part_of_speech("制造或修理钟表者"),
+            (None, "制造或修理钟表者".to_string())
+        );
+        assert_eq!(
+            split_part_of_speech("e.g. 例如"),
+            (None, "e.g. 例如".to_string())
+        );
+    }
+
+    #[test]
+    fn test_display_entry() {
+        let mut entry = Entry::from_dictionary("clock-maker", "n. 制造或修理钟表者");
+        entry.alternatives = vec!["clockmaker".to_string()];
+        entry.confidence = Some(0.9);
+        assert_eq!(
+            entry.to_string(),
+            "Translation: clock-maker\n  n. 制造或修理钟表者\nAlternatives: clockmaker\nConfidence: 90%"
+        );
+
+        entry.rare = vec!["clock-maker".to_string()];
+        assert!(
+            entry
+                .to_string()
+                .starts_with("Translation: clock-maker (rare)\n")
+        );
+    }
+
+    #[test]
+    fn test_display_failed_verification() {
+        let entry = Entry {
+            headword: "watch repair".to_string(),
+            gloss: Some("修理钟表".to_string()),
+            confidence: Some(0.9),
+            verification: Some(Verification::new("修理钟表", "维修手表", 0.5)),
+            ..Default::default()
+        };
+        let output = entry.to_string();
+        assert!(output.contains("Back-translation: 维修手表 (similarity 0%)"));
+        assert!(output.contains("Warning:"));
+        assert!(!output.contains("Confidence"));
+    }
+}
@@ -0,0 +1,144 @@
+use serde_json::{Value, json};
+use std::fmt;
+
+/// Everything that can make a command fail. Each kind exits with its own code so
+/// scripts can tell a missing translation from a network or configuration problem.
+#[derive(Debug)]
+pub enum Error {
+    /// Neither the translation memory nor the dictionaries (nor the AI service) had a translation
+    NotFound(String),
+    /// A dictionary file or the manifest couldn't be read
+    Dictionary(String),
+    /// Missing or invalid configuration, e.g. an unset API key or a malformed models.json
+    Config(String),
+    /// The AI service couldn't be reached
+    Network(String),
+    /// The AI service answered with an error status
+    ApiStatus { status: u16, body: String },
+    /// A response or a local data file couldn't be parsed
+    Parse(String),
+    /// Reading or writing a local file failed
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Process exit code. 2 is left to clap for usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) => 1,
+            Error::NotFound(_) => 3,
+            Error::Dictionary(_) => 4,
+            Error::Config(_) => 5,
+            Error::Network(_) => 6,
+            Error::ApiStatus { .. } => 7,
+            Error::Parse(_) => 8,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::Dictionary(_) => "dictionary",
+            Error::Config(_) => "config",
+            Error::Network(_) => "network",
+            Error::ApiStatus { .. } => "api_status",
+            Error::Parse(_) => "parse",
+            Error::Io(_) => "io",
+        }
+    }
+
+    /// The error as printed with `--json`.
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        if let Error::ApiStatus { status, .. } = self {
+            error["status"] = json!(status);
+        }
+        json!({ "error": error })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(text) => write!(
+                f,
+                "Translation not found for '{}'. Try using --ai flag to get AI translation.",
+                text
+            ),
+            Error::Dictionary(message) => write!(f, "Failed to load dictionary: {}", message),
+            Error::Config(message) | Error::Parse(message) => write!(f, "{}", message),
+            Error::Network(message) => write!(f, "Network error: {}", message),
+            Error::ApiStatus { status, body } => {
+                write!(f, "API request failed with status {}: {}", status, body)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<roxmltree::Error> for Error {
+    fn from(e: roxmltree::Error) -> Self {
+        Error::Parse(format!("Invalid TMX: {}", e))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Parse(format!("Invalid API response: {}", e))
+        } else {
+            Error::Network(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::NotFound("x".to_string()),
+            Error::Dictionary("x".to_string()),
+            Error::Config("x".to_string()),
+            Error::Network("x".to_string()),
+            Error::ApiStatus {
+                status: 401,
+                body: "x".to_string(),
+            },
+            Error::Parse("x".to_string()),
+            Error::Io(std::io::Error::other("x")),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0) && !codes.contains(&2));
+
+        let json = errors[4].to_json();
+        assert_eq!(json["error"]["kind"], "api_status");
+        assert_eq!(json["error"]["status"], 401);
+        assert_eq!(json["error"]["exit_code"], 7);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::chinese::{self, is_cjk_punctuation, is_han};
+use crate::dictionary::Dictionary;
+use crate::error::{Error, Result};
+use crate::memory::TranslationMemory;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// What `--filter` does with each run of Chinese text.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Keep the Chinese and follow it with the translation: `原文 [original text]`
+    Annotate,
+    /// Put the translation in place of the Chinese
+    Replace,
+}
+
+/// Translates the Chinese runs of a text stream line by line, passing everything
+/// else through unchanged.
+pub struct Filter<'a> {
+    pub dictionary: &'a Dictionary,
+    pub memory: &'a TranslationMemory,
+    pub options: crate::TranslateOptions,
+    pub memory_threshold: f32,
+    pub mode: Mode,
+    /// Script and pinyin of the Chinese runs written out
+    pub display: chinese::Display,
+    /// Logs repeat themselves, so each run is only translated once
+    cache: HashMap<String, Option<String>>,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(
+        dictionary: &'a Dictionary,
+        memory: &'a TranslationMemory,
+        options: crate::TranslateOptions,
+        memory_threshold: f32,
+        mode: Mode,
+        display: chinese::Display,
+    ) -> Self {
+        Self {
+            dictionary,
+            memory,
+            // A short run is part of many glosses, so only an exact definition will do
+            options: crate::TranslateOptions {
+                exact: true,
+                ..options
+            },
+            memory_threshold,
+            mode,
+            display,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Filter `input` to `output`, writing and flushing each line as soon as it's
+    /// translated so the filter works in a pipeline. Bytes that aren't valid UTF-8,
+    /// such as binary noise in a log, are passed through unchanged.
+    pub async fn run(
+        &mut self,
+        mut input: impl AsyncBufRead + Unpin,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        let mut line = Vec::new();
+        while input.read_until(b'\n', &mut line).await? > 0 {
+            for chunk in line.utf8_chunks() {
+                let filtered = self.line(chunk.valid()).await?;
+                output.write_all(filtered.as_bytes())?;
+                output.write_all(chunk.invalid())?;
+            }
+            output.flush()?;
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Translate the Chinese runs of one line. A run without a translation is left as it is.
+    pub async fn line(&mut self, line: &str) -> Result<String> {
+        let mut filtered = String::new();
+        let mut end = 0;
+        for run in runs(line) {
+            let before = &line[end..run.start];
+            let text = &line[run.clone()];
+            filtered.push_str(before);
+            match (self.lookup(text).await?, self.mode) {
+                (Some(translation), Mode::Annotate) => {
+                    filtered.push_str(&format!("{} [{}]", self.display.apply(text), translation));
+                }
+                (Some(translation), Mode::Replace) => {
+                    // Keep the translation from running into neighbouring words
+                    if before.ends_with(|c: char| c.is_alphanumeric()) {
+                        filtered.push(' ');
+                    }
+                    filtered.push_str(&translation);
+                    if line[run.end..].starts_with(|c: char| c.is_alphanumeric()) {
+                        filtered.push(' ');
+                    }
+                }
+                (None, _) => filtered.push_str(&self.display.apply(text)),
+            }
+            end = run.end;
+        }
+        filtered.push_str(&line[end..]);
+        Ok(filtered)
+    }
+
+    /// The translation memory, then the exact dictionary definitions, then (with `--ai`) the AI service.
+    /// Network and API errors are reported and the run is left untranslated, so one
+    /// failed request doesn't end the stream.
+    async fn lookup(&mut self, text: &str) -> Result<Option<String>> {
+        if let Some(translation) = self.cache.get(text) {
+            return Ok(translation.clone());
+        }
+        let normalized = chinese::normalize_input(text);
+        let translation = match self.memory.lookup(&normalized, self.memory_threshold) {
+            Some(entry) => Some(entry.headword),
+            None => match crate::translate(self.dictionary, &normalized, &self.options).await {
+                Ok(entry) => Some(entry.headword),
+                Err(Error::NotFound(_)) => None,
+                Err(e @ (Error::Network(_) | Error::ApiStatus { .. } | Error::Parse(_))) => {
+                    eprintln!("Warning: failed to translate '{}': {}", text, e);
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        self.cache.insert(text.to_string(), translation.clone());
+        Ok(translation)
+    }
+}
+
+/// Byte ranges of the runs of Chinese in `line`. A run starts and ends with a Han
+/// character and may have Chinese punctuation inside, as in `错误：文件不存在`.
+fn runs(line: &str) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    for (i, c) in line.char_indices() {
+        if is_han(c) {
+            let end = i + c.len_utf8();
+            match &mut current {
+                Some(run) => run.end = end,
+                None => current = Some(i..end),
+            }
+        } else if !is_cjk_punctuation(c) {
+            runs.extend(current.take());
+        }
+    }
+    runs.extend(current);
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, chat_reply, fixture_dictionary};
+
+    #[test]
+    fn test_runs() {
+        let line = "E0042 错误：文件不存在。 see 日志 (log)";
+        let texts: Vec<&str> = runs(line).into_iter().map(|run| &line[run]).collect();
+        assert_eq!(texts, vec!["错误：文件不存在", "日志"]);
+        assert!(runs("no Chinese here, only ：punctuation").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let server = MockServer::start(vec![
+            (500, "overloaded".to_string()),
+            chat_reply(r#"{"translation": "cannot connect to the server"}"#),
+        ])
+        .await;
+        let dictionary = fixture_dictionary();
+        let memory = TranslationMemory::default();
+        let options = crate::TranslateOptions {
+            use_ai: true,
+            ai_service: Some(server.service()),
+            ..Default::default()
+        };
+        let mut filter = Filter::new(
+            &dictionary,
+            &memory,
+            options,
+            1.0,
+            Mode::Annotate,
+            chinese::Display::default(),
+        );
+        let input = "commit 1a2b3c\n    修复时钟\nerror: 无法连接到服务器\nretry: 无法连接到服务器";
+        let mut output = Vec::new();
+        filter.run(input.as_bytes(), &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "commit 1a2b3c\n    修复时钟\nerror: 无法连接到服务器 [cannot connect to the server]\nretry: 无法连接到服务器 [cannot connect to the server]"
+        );
+        // The failed run was left alone and the repeated one translated only once
+        assert_eq!(server.requests().len(), 2);
+
+        // Invalid UTF-8 doesn't end the stream
+        let input = b"ok \xe6\x97\xb6\xe9\x92\x9f\n\xff\xfe x\nafter \xe6\x97\xb6\xe9\x92\x9f\n";
+        let mut output = Vec::new();
+        filter.run(&input[..], &mut output).await.unwrap();
+        assert_eq!(output, b"ok \xe6\x97\xb6\xe9\x92\x9f [clock]\n\xff\xfe x\nafter \xe6\x97\xb6\xe9\x92\x9f [clock]\n");
+
+        filter.display = chinese::Display {
+            script: Some(chinese::Script::Traditional),
+            pinyin: Some(chinese::PinyinStyle::Marks),
+        };
+        assert_eq!(
+            filter.line("时钟 and 修复时钟").await.unwrap(),
+            "時鐘 (shí zhōng) [clock] and 修復時鐘 (xiū fù shí zhōng)"
+        );
+
+        // A single character is part of unrelated glosses but not a definition of its own
+        filter.options.use_ai = false;
+        filter.display = chinese::Display::default();
+        assert_eq!(
+            filter.line("error: 修 failed, 钟 ok").await.unwrap(),
+            "error: 修 failed, 钟 [clock] ok"
+        );
+
+        filter.mode = Mode::Replace;
+        assert_eq!(filter.line("时钟stopped").await.unwrap(), "clock stopped");
+    }
+}
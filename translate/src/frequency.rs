@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+/// Words less frequent than this, per million words of the corpus, are marked rare.
+pub const RARE_PER_MILLION: f32 = 1.0;
+
+/// Column names of the count in known word-frequency lists, e.g. SUBTLEX-US's `FREQcount`.
+const COUNT_COLUMNS: [&str; 5] = ["freqcount", "count", "freq", "frequency", "wcount"];
+
+/// Word frequencies from corpus word lists, per million words so lists of different
+/// sizes can be combined. A word in more than one list keeps its highest frequency.
+#[derive(Debug, Default)]
+pub struct Frequencies {
+    per_million: HashMap<String, f32>,
+    lists: usize,
+}
+
+impl Frequencies {
+    /// Add a word list: a `word count` TSV, or a list with a header row such as SUBTLEX.
+    /// Returns the number of words read.
+    pub fn add_list(&mut self, content: &str) -> usize {
+        let counts = parse(content);
+        let total: f64 = counts.iter().map(|(_, count)| count).sum();
+        if total <= 0.0 {
+            return 0;
+        }
+        for (word, count) in &counts {
+            let per_million = (count / total * 1_000_000.0) as f32;
+            let frequency = self.per_million.entry(word.clone()).or_insert(0.0);
+            *frequency = frequency.max(per_million);
+        }
+        self.lists += 1;
+        counts.len()
+    }
+
+    pub fn lists(&self) -> usize {
+        self.lists
+    }
+
+    pub fn len(&self) -> usize {
+        self.per_million.len()
+    }
+
+    pub fn get(&self, word: &str) -> Option<f32> {
+        self.per_million.get(&word.to_lowercase()).copied()
+    }
+
+    /// Whether a word is rare or archaic: listed, but below `RARE_PER_MILLION`. Words the
+    /// lists don't have, such as domain terms and product names, are unknown rather than rare.
+    pub fn is_rare(&self, word: &str) -> bool {
+        self.get(word)
+            .is_some_and(|frequency| frequency < RARE_PER_MILLION)
+    }
+}
+
+/// Words and counts of a list separated by tabs, or by spaces when there are no tabs.
+/// A first line whose count isn't a number is a header naming the columns.
+fn parse(content: &str) -> Vec<(String, f64)> {
+    let split = |line: &str| -> Vec<String> {
+        if line.contains('\t') {
+            line.split('\t')
+                .map(|field| field.trim().to_string())
+                .collect()
+        } else {
+            line.split_whitespace().map(str::to_string).collect()
+        }
+    };
+    let mut lines = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    let (mut word_column, mut count_column) = (0, 1);
+    if let Some(first) = lines.peek() {
+        let header = split(first);
+        if header
+            .get(1)
+            .is_none_or(|field| field.parse::<f64>().is_err())
+        {
+            let names: Vec<String> = header.iter().map(|name| name.to_lowercase()).collect();
+            word_column = names.iter().position(|name| name == "word").unwrap_or(0);
+            count_column = COUNT_COLUMNS
+                .iter()
+                .find_map(|column| names.iter().position(|name| name == column))
+                .unwrap_or(1);
+            lines.next();
+        }
+    }
+    lines
+        .filter_map(|line| {
+            let fields = split(line);
+            let word = fields.get(word_column)?.to_lowercase();
+            let count = fields.get(count_column)?.parse::<f64>().ok()?;
+            (!word.is_empty() && count >= 0.0).then_some((word, count))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists() {
+        let mut frequencies = Frequencies::default();
+        assert!(!frequencies.is_rare("whilom"));
+
+        let subtlex = "Word\tFREQcount\tCDcount\tSUBTLWF\nClock\t9000\t800\t17.6\nhello\t1000\t900\t2.0\nwhilom\t0\t0\t0\n";
+        assert_eq!(frequencies.add_list(subtlex), 3);
+        assert_eq!(frequencies.add_list("clockmaker 1000\nclock 1000\n"), 2);
+        assert_eq!(frequencies.get("clock"), Some(900_000.0));
+        assert_eq!(frequencies.get("Clockmaker"), Some(500_000.0));
+
+        assert!(frequencies.is_rare("whilom"));
+        assert!(!frequencies.is_rare("clock-maker"));
+        assert!(!frequencies.is_rare("clockmaker"));
+        assert!(!frequencies.is_rare("computer virus"));
+    }
+}
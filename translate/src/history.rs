@@ -0,0 +1,239 @@
+use crate::error::{Error, Result};
+use chrono::{Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// History is only recorded when `TRANSLATE_HISTORY` is set to a truthy value,
+/// since the PRD asks us not to keep users' queries by default.
+pub fn is_enabled() -> bool {
+    env::var("TRANSLATE_HISTORY")
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "on" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Spaced-repetition state of one card, following the SM-2 algorithm.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Card {
+    pub repetitions: u32,
+    pub interval_days: u32,
+    pub ease: f32,
+    pub due: NaiveDate,
+}
+
+impl Card {
+    fn new(today: NaiveDate) -> Self {
+        Self {
+            repetitions: 0,
+            interval_days: 0,
+            ease: 2.5,
+            due: today,
+        }
+    }
+
+    /// Update the card after a review graded from 0 (forgotten) to 5 (perfect recall).
+    pub fn review(&mut self, quality: u8, today: NaiveDate) {
+        let quality = quality.min(5);
+        if quality >= 3 {
+            self.interval_days = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval_days as f32 * self.ease).round() as u32,
+            };
+            self.repetitions += 1;
+        } else {
+            self.repetitions = 0;
+            self.interval_days = 1;
+        }
+        let q = (5 - quality) as f32;
+        self.ease = (self.ease + 0.1 - q * (0.08 + q * 0.02)).max(1.3);
+        self.due = today + Duration::days(self.interval_days as i64);
+    }
+}
+
+/// Which side of a word the quiz shows first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ChineseToEnglish,
+    EnglishToChinese,
+}
+
+/// A looked-up word together with its review cards in both directions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Item {
+    pub source: String,
+    pub translation: String,
+    pub lookups: u32,
+    pub last_lookup: NaiveDate,
+    pub zh_en: Card,
+    pub en_zh: Card,
+}
+
+impl Item {
+    pub fn card_mut(&mut self, direction: Direction) -> &mut Card {
+        match direction {
+            Direction::ChineseToEnglish => &mut self.zh_en,
+            Direction::EnglishToChinese => &mut self.en_zh,
+        }
+    }
+
+    pub fn card(&self, direction: Direction) -> &Card {
+        match direction {
+            Direction::ChineseToEnglish => &self.zh_en,
+            Direction::EnglishToChinese => &self.en_zh,
+        }
+    }
+}
+
+/// Looked-up words and quiz progress, stored in `~/.translate/history.json`.
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    pub items: Vec<Item>,
+}
+
+impl History {
+    pub fn open() -> Result<Self> {
+        Self::load(&crate::data_dir()?.join("history.json"))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let items = if path.exists() {
+            let data = fs::read_to_string(path)?;
+            serde_json::from_str(&data)
+                .map_err(|e| Error::Parse(format!("Invalid history {}: {}", path.display(), e)))?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            items,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.items)?)?;
+        Ok(())
+    }
+
+    /// Record a lookup, keeping the review progress of words seen before.
+    pub fn record(&mut self, source: &str, translation: &str, today: NaiveDate) {
+        let source = source.trim();
+        let translation = translation.trim();
+        if source.is_empty() || translation.is_empty() {
+            return;
+        }
+        match self
+            .items
+            .iter_mut()
+            .find(|item| item.source == source && item.translation == translation)
+        {
+            Some(item) => {
+                item.lookups += 1;
+                item.last_lookup = today;
+            }
+            None => self.items.push(Item {
+                source: source.to_string(),
+                translation: translation.to_string(),
+                lookups: 1,
+                last_lookup: today,
+                zh_en: Card::new(today),
+                en_zh: Card::new(today),
+            }),
+        }
+    }
+
+    /// Cards due on or before `today`, most overdue first, as (item index, direction).
+    pub fn due(&self, directions: &[Direction], today: NaiveDate) -> Vec<(usize, Direction)> {
+        let mut due: Vec<(usize, Direction)> = self
+            .items
+            .iter()
+            .enumerate()
+            .flat_map(|(i, item)| {
+                directions
+                    .iter()
+                    .filter(move |d| item.card(**d).due <= today)
+                    .map(move |d| (i, *d))
+            })
+            .collect();
+        due.sort_by_key(|(i, d)| self.items[*i].card(*d).due);
+        due
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+}
+
+/// Record a lookup if history is enabled. Failures only produce a warning.
+pub fn record_lookup(source: &str, translation: &str) {
+    if !is_enabled() {
+        return;
+    }
+    let result = History::open().and_then(|mut history| {
+        history.record(source, translation, Local::now().date_naive());
+        history.save()
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: failed to record history: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 9, d).unwrap()
+    }
+
+    #[test]
+    fn test_sm2_review() {
+        let mut card = Card::new(day(1));
+        card.review(5, day(1));
+        assert_eq!(
+            (card.repetitions, card.interval_days, card.due),
+            (1, 1, day(2))
+        );
+        assert!((card.ease - 2.6).abs() < 1e-6);
+
+        card.review(4, day(2));
+        assert_eq!(
+            (card.repetitions, card.interval_days, card.due),
+            (2, 6, day(8))
+        );
+
+        card.review(3, day(8));
+        // 6 days * 2.6 ease
+        assert_eq!(card.interval_days, 16);
+        assert!((card.ease - 2.46).abs() < 1e-5);
+
+        card.review(1, day(24));
+        assert_eq!(
+            (card.repetitions, card.interval_days, card.due),
+            (0, 1, day(25))
+        );
+        assert!(card.ease >= 1.3);
+    }
+
+    #[test]
+    fn test_record_and_due() {
+        let mut history = History::default();
+        history.record("钟表匠", "clockmaker", day(1));
+        history.record("钟表匠", "clockmaker", day(2));
+        history.record("你好", "hello", day(2));
+        assert_eq!(history.items.len(), 2);
+        assert_eq!(history.items[0].lookups, 2);
+
+        let both = [Direction::ChineseToEnglish, Direction::EnglishToChinese];
+        assert_eq!(history.due(&both, day(2)).len(), 4);
+
+        history.items[0].zh_en.review(5, day(2));
+        let due = history.due(&[Direction::ChineseToEnglish], day(2));
+        assert_eq!(due, vec![(1, Direction::ChineseToEnglish)]);
+    }
+}
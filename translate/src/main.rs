@@ -1,221 +1,1012 @@
-use clap::Parser;
+mod ai;
+mod anki;
+mod catalog;
+mod chinese;
+mod chunk;
+mod commit;
+mod compare;
+mod coverage;
+mod dictionary;
+mod entry;
+mod error;
+mod filter;
+mod frequency;
+mod history;
+mod memory;
+mod network;
+mod placeholder;
+mod quiz;
+mod rpc;
+mod search;
+mod subs;
+#[cfg(test)]
+mod testing;
+mod usage;
+mod verify;
+
+use ai::AITranslationService;
+use chinese::{PinyinStyle, Script};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use dictionary::{Dictionary, DomainFilter};
 use dotenvy::dotenv;
-use std::collections::HashMap;
+use entry::Entry;
+use error::{Error, Result};
+use history::{Direction, History};
+use memory::TranslationMemory;
+use serde_json::Value;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use verify::Verification;
 
 /// A simple CLI tool for translating Chinese to English.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+#[command(override_usage = "translate [OPTIONS] [TEXT]\n       translate [OPTIONS] <COMMAND>")]
 struct Args {
     /// The Chinese text to translate
-    text: String,
+    #[arg(required_unless_present_any = ["stdio", "filter"])]
+    text: Option<String>,
+
+    /// Serve line-delimited JSON-RPC on stdin/stdout for editor integration
+    #[arg(long, conflicts_with = "text")]
+    stdio: bool,
+
+    /// Copy stdin to stdout line by line, translating only the runs of Chinese
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with_all = ["text", "stdio"], num_args = 0..=1, require_equals = true, default_missing_value = "annotate")]
+    filter: Option<filter::Mode>,
 
     /// Use AI translation service
     #[arg(long)]
     ai: bool,
+
+    /// Show details such as token usage of AI requests
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Send the text to several models concurrently and compare their answers
+    #[arg(long, value_name = "MODELS", value_delimiter = ',')]
+    compare_models: Vec<String>,
+
+    /// Translate AI results back to Chinese and warn when the meaning has drifted
+    #[arg(long)]
+    verify: bool,
+
+    /// Minimum back-translation similarity (0.0-1.0) for --verify
+    #[arg(long, value_name = "SCORE", default_value_t = verify::DEFAULT_THRESHOLD)]
+    verify_threshold: f32,
+
+    /// Prefer dictionaries tagged with this domain (repeatable, e.g. computing, medical, team)
+    #[arg(long = "domain", global = true, value_name = "TAG")]
+    domains: Vec<String>,
+
+    /// Only look up dictionaries tagged with one of the --domain tags
+    #[arg(long, global = true, requires = "domains")]
+    domain_only: bool,
+
+    /// Minimum match score (0.0-1.0) for reusing a translation memory segment
+    #[arg(long, global = true, value_name = "SCORE", default_value_t = memory::DEFAULT_THRESHOLD)]
+    memory_threshold: f32,
+
+    /// Save the translation to the translation memory
+    #[arg(long)]
+    remember: bool,
+
+    /// Split AI input longer than this many estimated tokens at sentence boundaries
+    #[arg(long, value_name = "TOKENS", default_value_t = chunk::DEFAULT_CHUNK_TOKENS)]
+    chunk_tokens: usize,
+
+    /// Maximum number of chunks of a long text translated at the same time
+    #[arg(long, value_name = "N", default_value_t = chunk::DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+
+    /// Print machine-readable JSON output, including errors
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Follow Chinese text with its pinyin, using tone marks or tone numbers
+    #[arg(long, global = true, value_enum, value_name = "STYLE", num_args = 0..=1, require_equals = true, default_missing_value = "marks")]
+    pinyin: Option<PinyinStyle>,
+
+    /// Print Chinese text in simplified or traditional characters
+    #[arg(long, global = true, value_enum)]
+    script: Option<Script>,
+
+    #[command(flatten)]
+    network: network::NetworkConfig,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-// AI translation service implementation
-struct AITranslationService {
-    api_key: String,
-    base_url: String,
-    model: String,
+impl Args {
+    /// Parse the command line. The shared options are `global`, so they may come before
+    /// or after a subcommand, but the options of a plain translation can't be given with one.
+    fn try_parse_args<I, T>(args: I) -> std::result::Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args = Self::try_parse_from(args)?;
+        if args.command.is_some() {
+            let translation_only = [
+                ("[TEXT]", args.text.is_some()),
+                ("--stdio", args.stdio),
+                ("--filter", args.filter.is_some()),
+                ("--ai", args.ai),
+                ("--compare-models", !args.compare_models.is_empty()),
+                ("--verify", args.verify),
+                ("--remember", args.remember),
+            ];
+            if let Some((name, _)) = translation_only.iter().find(|(_, used)| *used) {
+                return Err(Self::command().error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!("{} cannot be used with a subcommand", name),
+                ));
+            }
+        }
+        Ok(args)
+    }
+
+    /// The --domain tags, restricting lookups with --domain-only.
+    fn domain_filter(&self) -> DomainFilter {
+        DomainFilter {
+            tags: self.domains.clone(),
+            restrict: self.domain_only,
+        }
+    }
 }
 
-impl AITranslationService {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Get API key from environment
-        let api_key = env::var("OPENAI_API_KEY")
-            .map_err(|_| "OPENAI_API_KEY environment variable not set")?;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show AI token usage and cost for today, this week and this month
+    Usage,
+    /// Check the dictionaries and report conflicting definitions
+    Validate,
+    /// Find words by meaning, ranking dictionary glosses that match the query
+    Search {
+        /// Chinese words describing the meaning, e.g. "修理 钟"; `*` and `?` are wildcards
+        query: String,
 
-        // Get base URL from environment or use default
-        let base_url = env::var("OPENAI_BASE_URL")
-            .unwrap_or_else(|_| "https://api.openai.com/v1/".to_string());
+        /// Maximum number of results
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Report how much of a Chinese document the dictionaries cover
+    Coverage {
+        /// The document to check
+        file: PathBuf,
 
-        // Get model from environment or use default
-        let model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
+        /// Number of unknown terms and candidate entries to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Translate the Chinese text of an SRT or WebVTT subtitle file with the AI service
+    Subs {
+        /// The subtitle file to translate
+        input: PathBuf,
 
-        Ok(Self {
-            api_key,
-            base_url,
-            model,
-        })
-    }
+        /// Where to write the translated subtitles, instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Keep the original lines and add the translation under them
+        #[arg(long)]
+        bilingual: bool,
+
+        /// Number of cues sent in one request
+        #[arg(long, value_name = "N", default_value_t = chunk::DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Maximum number of requests at the same time
+        #[arg(long, value_name = "N", default_value_t = chunk::DEFAULT_CONCURRENCY)]
+        concurrency: usize,
+    },
+    /// Translate a commit message to English, keeping its type prefix, trailers and issue references
+    Commit {
+        /// The message file, as passed to a prepare-commit-msg hook; standard input if omitted
+        file: Option<PathBuf>,
+
+        /// Overwrite the file instead of printing the translation
+        #[arg(short, long, requires = "file")]
+        write: bool,
+    },
+    /// Fill the untranslated entries of a gettext PO file or a JSON locale file
+    Catalog {
+        /// The PO file, or the JSON locale file to fill (created if it doesn't exist)
+        file: PathBuf,
+
+        /// The Chinese JSON locale file the strings come from; required for JSON
+        #[arg(long)]
+        source: Option<PathBuf>,
+
+        /// Where to write the result, instead of overwriting the file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Use the AI service for strings the memory and dictionaries don't have
+        #[arg(long)]
+        ai: bool,
+
+        /// Number of strings sent in one request
+        #[arg(long, value_name = "N", default_value_t = chunk::DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Maximum number of requests at the same time
+        #[arg(long, value_name = "N", default_value_t = chunk::DEFAULT_CONCURRENCY)]
+        concurrency: usize,
+    },
+    /// Export words for studying elsewhere
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Review looked-up words as flashcards with spaced repetition
+    Quiz {
+        /// Which side of each word to show first
+        #[arg(long, value_enum, default_value_t = QuizDirection::Both)]
+        direction: QuizDirection,
+
+        /// Maximum number of cards to review
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// List recorded lookups (recording is enabled with TRANSLATE_HISTORY=1)
+    History {
+        /// Delete all recorded lookups and quiz progress
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Manage the translation memory of previously translated sentences
+    #[command(alias = "memory")]
+    Tm {
+        #[command(subcommand)]
+        action: MemoryAction,
+    },
 }
 
-#[derive(serde::Serialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum QuizDirection {
+    /// Chinese to English
+    ZhEn,
+    /// English to Chinese
+    EnZh,
+    Both,
 }
 
-#[derive(serde::Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
+#[derive(Subcommand, Debug)]
+enum ExportFormat {
+    /// Write an Anki package (.apkg) with a card each way for every word
+    Anki {
+        /// Where the words come from
+        #[arg(long, value_enum, default_value_t = ExportSource::History)]
+        from: ExportSource,
+
+        /// The word list for `--from file`: one word, or `中文<TAB>English`, per line
+        #[arg(required_if_eq("from", "file"))]
+        file: Option<PathBuf>,
+
+        /// The package to write
+        #[arg(short, long, default_value = "translate.apkg")]
+        output: PathBuf,
+
+        /// Name of the Anki deck
+        #[arg(long, default_value = "translate")]
+        deck: String,
+    },
 }
 
-#[derive(serde::Deserialize)]
-struct ChatChoice {
-    message: ChatResponseMessage,
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportSource {
+    /// The recorded lookups
+    History,
+    /// Every dictionary entry, or those of the --domain dictionaries with --domain-only
+    Dict,
+    /// A word list file
+    File,
 }
 
-#[derive(serde::Deserialize)]
-struct ChatResponseMessage {
-    content: String,
+#[derive(Subcommand, Debug)]
+enum MemoryAction {
+    /// Add a Chinese sentence and its English translation
+    Add { source: String, target: String },
+    /// Import segment pairs from a TMX file
+    Import { file: PathBuf },
+    /// Export the memory as a TMX file
+    Export { file: PathBuf },
 }
 
-#[derive(serde::Deserialize)]
-struct ChatResponse {
-    choices: Vec<ChatChoice>,
+/// Options that control a single translation.
+#[derive(Debug, Default, Clone)]
+struct TranslateOptions {
+    /// Fall back to the AI service when the dictionary has no match
+    use_ai: bool,
+    /// Print extra diagnostics such as token usage
+    verbose: bool,
+    /// Back-translate AI results and flag them when the similarity is below `verify_threshold`
+    verify: bool,
+    verify_threshold: f32,
+    /// Domain tags that restrict or boost dictionary lookups
+    domains: DomainFilter,
+    /// Only use dictionary definitions with a segment equal to the text, not ones containing it
+    exact: bool,
+    /// Token budget of one AI request; longer text is split into chunks. 0 never splits
+    chunk_tokens: usize,
+    /// Maximum number of chunks translated at the same time
+    concurrency: usize,
+    /// The AI service to use instead of the one configured by the `OPENAI_*` variables
+    ai_service: Option<AITranslationService>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Load environment variables from .env file
+    dotenv().ok();
+
+    let args = Args::try_parse_args(env::args_os()).unwrap_or_else(|e| e.exit());
+    let json = args.json;
+    network::configure(args.network.clone().with_env());
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // With --json the error goes to stdout like any other result
+            if json {
+                println!("{}", e.to_json());
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
 }
 
-impl AITranslationService {
-    async fn translate(&self, text: &str) -> Result<String, Box<dyn std::error::Error>> {
-        // Create the request
-        let request = ChatRequest {
-            model: self.model.clone(),
-            messages: vec![
-                ChatMessage {
-                    role: "system".to_string(),
-                    content: "You are a professional translator. Translate the following Chinese text to English.".to_string(),
+async fn run(args: Args) -> Result<()> {
+    let display = chinese::Display {
+        script: args.script,
+        pinyin: args.pinyin,
+    };
+    let domains = args.domain_filter();
+
+    match args.command {
+        Some(Command::Usage) => {
+            println!("{}", usage::report()?);
+            return Ok(());
+        }
+        Some(Command::Validate) => {
+            let dictionary = load_dictionary()?;
+            println!(
+                "{}",
+                display.apply(&dictionary::validation_report(&dictionary))
+            );
+            return Ok(());
+        }
+        Some(Command::Search { query, limit }) => {
+            let dictionary = load_dictionary()?;
+            let hits = search::search(&dictionary, &chinese::normalize_input(&query), limit);
+            if hits.is_empty() {
+                println!("No matching entries");
+            }
+            for hit in hits {
+                println!("{}\t{}", hit.headword, display.apply(hit.gloss));
+            }
+            return Ok(());
+        }
+        Some(Command::Coverage { file, top }) => {
+            let dictionary = load_dictionary()?;
+            let vocabulary = coverage::vocabulary(&dictionary, &domains);
+            let text = chinese::normalize_input(&fs::read_to_string(file)?);
+            let report = coverage::report(&text, &vocabulary, top);
+            if args.json {
+                println!("{}", serde_json::to_string(&report)?);
+            } else {
+                print!("{}", display.apply(&report.to_string()));
+            }
+            return Ok(());
+        }
+        Some(Command::Subs {
+            input,
+            output,
+            bilingual,
+            batch_size,
+            concurrency,
+        }) => {
+            let service = AITranslationService::new()?;
+            let mut subtitles = subs::parse(&fs::read_to_string(&input)?);
+            let translated = subtitles
+                .translate(&service, bilingual, batch_size, concurrency)
+                .await?;
+            match output {
+                Some(path) => fs::write(path, subtitles.to_string())?,
+                None => print!("{}", subtitles),
+            }
+            eprintln!("Translated {} cues", translated);
+            return Ok(());
+        }
+        Some(Command::Commit { file, write }) => {
+            let content = match &file {
+                Some(path) => fs::read_to_string(path)?,
+                None => std::io::read_to_string(std::io::stdin())?,
+            };
+            let mut message = commit::parse(&content);
+            if message.has_chinese() {
+                message.translate(&AITranslationService::new()?).await?;
+            }
+            match file {
+                Some(path) if write => fs::write(path, message.to_string())?,
+                _ => print!("{}", message),
+            }
+            return Ok(());
+        }
+        Some(Command::Catalog {
+            file,
+            source,
+            output,
+            ai,
+            batch_size,
+            concurrency,
+        }) => {
+            let dictionary = load_dictionary()?;
+            let memory = open_memory_or_warn();
+            let filler = catalog::Filler {
+                dictionary: &dictionary,
+                memory: &memory,
+                options: TranslateOptions {
+                    use_ai: ai,
+                    verbose: args.verbose,
+                    domains,
+                    concurrency,
+                    ..Default::default()
                 },
-                ChatMessage {
-                    role: "user".to_string(),
-                    content: text.to_string(),
+                memory_threshold: args.memory_threshold,
+                batch_size,
+            };
+            return run_catalog(&file, source.as_deref(), output.as_deref(), &filler).await;
+        }
+        Some(Command::Export {
+            format:
+                ExportFormat::Anki {
+                    from,
+                    file,
+                    output,
+                    deck,
                 },
-            ],
+        }) => {
+            let dictionary = load_dictionary()?;
+            let notes = match (from, file) {
+                (ExportSource::History, _) => {
+                    anki::notes_from_history(&History::open()?, &dictionary)
+                }
+                (ExportSource::Dict, _) => anki::notes_from_dictionary(&dictionary, &domains),
+                (ExportSource::File, file) => {
+                    let list = fs::read_to_string(file.unwrap_or_default())?;
+                    anki::notes_from_list(&list, &dictionary, &domains)
+                }
+            };
+            let now = chrono::Utc::now().timestamp_millis();
+            fs::write(&output, anki::package(&notes, &deck, now)?)?;
+            println!(
+                "Exported {} notes ({} cards) to {}",
+                notes.len(),
+                notes.len() * 2,
+                output.display()
+            );
+            return Ok(());
+        }
+        Some(Command::Quiz { direction, limit }) => return run_quiz(direction, limit, display),
+        Some(Command::History { clear }) => return run_history(clear, display),
+        Some(Command::Tm { action }) => return run_memory_action(action),
+        None => {}
+    }
+
+    if args.stdio {
+        let dictionary = load_dictionary()?;
+        let memory = open_memory_or_warn();
+        let defaults = TranslateOptions {
+            verify_threshold: args.verify_threshold,
+            domains,
+            chunk_tokens: args.chunk_tokens,
+            concurrency: args.concurrency,
+            ..Default::default()
+        };
+        let server = rpc::Server::new(dictionary, memory, defaults, args.memory_threshold);
+        return server.serve().await;
+    }
+
+    if let Some(mode) = args.filter {
+        let dictionary = load_dictionary()?;
+        let memory = open_memory_or_warn();
+        let options = TranslateOptions {
+            use_ai: args.ai,
+            verbose: args.verbose,
+            domains,
+            chunk_tokens: args.chunk_tokens,
+            concurrency: args.concurrency,
+            ..Default::default()
         };
+        let mut filter = filter::Filter::new(
+            &dictionary,
+            &memory,
+            options,
+            args.memory_threshold,
+            mode,
+            display,
+        );
+        let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+        return filter.run(stdin, &mut std::io::stdout()).await;
+    }
 
-        // Make the HTTP request
-        let client = reqwest::Client::new();
-        let url = format!("{}chat/completions", self.base_url);
+    // Traditional input still finds the simplified dictionaries and memory
+    let text = chinese::normalize_input(&args.text.unwrap_or_default());
 
-        let response = client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    if !args.compare_models.is_empty() {
+        let (comparison, error) = compare::compare(&text, &args.compare_models).await;
+        if args.json {
+            // When no model answered, the error object is the record instead
+            if let Some(error) = error {
+                return Err(error);
+            }
+            println!("{}", display.convert(&serde_json::to_string(&comparison)?));
+        } else {
+            println!("{}", display.apply(&compare::render(&comparison)));
+        }
+        return error.map_or(Ok(()), Err);
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(
-                format!("API request failed with status {}: {}", status, error_text).into(),
-            );
+    let options = TranslateOptions {
+        use_ai: args.ai,
+        verbose: args.verbose,
+        verify: args.verify,
+        verify_threshold: args.verify_threshold,
+        domains,
+        exact: false,
+        chunk_tokens: args.chunk_tokens,
+        concurrency: args.concurrency,
+        ai_service: None,
+    };
+    let print = |entry: &Entry| -> Result<()> {
+        if args.json {
+            println!("{}", display.convert(&serde_json::to_string(entry)?));
+        } else {
+            println!("{}", display.apply(&entry.to_string()));
         }
+        Ok(())
+    };
 
-        let response_body: ChatResponse = response.json().await?;
-        let translation = response_body
-            .choices
-            .first()
-            .map(|choice| &choice.message.content)
-            .ok_or("No translation received from API")?;
+    // Reuse a previous translation when the memory has a close enough match
+    let mut memory = open_memory_or_warn();
+    if let Some(entry) = memory.lookup(&text, args.memory_threshold) {
+        print(&entry)?;
+        history::record_lookup(&text, &entry.headword);
+        return Ok(());
+    }
 
-        Ok(translation.clone())
+    // Load dictionary and perform translation
+    let dictionary = load_dictionary()?;
+    let entry = translate(&dictionary, &text, &options).await?;
+    print(&entry)?;
+    history::record_lookup(&text, &entry.headword);
+    if args.remember {
+        memory.add(&text, &entry.headword);
+        if let Err(e) = memory.save() {
+            eprintln!("Warning: failed to save translation memory: {}", e);
+        }
     }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    // Load environment variables from .env file
-    dotenv().ok();
-
-    let args = Args::parse();
+async fn run_catalog(
+    file: &Path,
+    source: Option<&Path>,
+    output: Option<&Path>,
+    filler: &catalog::Filler<'_>,
+) -> Result<()> {
+    let output = output.unwrap_or(file);
+    if file
+        .extension()
+        .is_some_and(|ext| ext == "po" || ext == "pot")
+    {
+        let mut po = catalog::parse_po(&fs::read_to_string(file)?);
+        let (filled, untranslated) = po.fill(filler).await?;
+        fs::write(output, po.to_string())?;
+        println!(
+            "Filled {} of {} untranslated entries, marked fuzzy",
+            filled, untranslated
+        );
+        return Ok(());
+    }
 
-    // Load dictionary
-    let dictionary = match load_dictionary() {
-        Ok(dict) => dict,
-        Err(e) => {
-            eprintln!("Error loading dictionary: {}", e);
-            return;
+    let source = source.ok_or_else(|| {
+        Error::Config("JSON catalogs need --source, the Chinese locale file".to_string())
+    })?;
+    let source: Value = serde_json::from_str(&fs::read_to_string(source)?)?;
+    let target: Option<Value> = match fs::read_to_string(file) {
+        Ok(data) => Some(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let mut pending = Vec::new();
+    let mut merged = catalog::merge_json(&source, target.as_ref(), &mut pending);
+    let texts: Vec<String> = pending.iter().map(|(_, text)| text.clone()).collect();
+    let translations = filler.fill(&texts).await?;
+    let mut filled = Vec::new();
+    for ((path, _), translation) in pending.iter().zip(translations) {
+        if let Some(translation) = translation {
+            catalog::set_json(&mut merged, path, &translation);
+            filled.push(catalog::json_pointer(path));
         }
+    }
+    fs::write(output, serde_json::to_string_pretty(&merged)? + "\n")?;
+
+    let fuzzy_path = catalog::fuzzy_path(output);
+    let previous: Option<Value> = match fs::read_to_string(&fuzzy_path) {
+        Ok(data) => Some(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
     };
+    let fuzzy = catalog::fuzzy_list(&merged, previous.as_ref(), &filled);
+    if !fuzzy.is_empty() {
+        fs::write(&fuzzy_path, serde_json::to_string_pretty(&fuzzy)? + "\n")?;
+    } else if previous.is_some() {
+        fs::remove_file(&fuzzy_path)?;
+    }
+    println!(
+        "Filled {} of {} untranslated entries",
+        filled.len(),
+        pending.len()
+    );
+    if !fuzzy.is_empty() {
+        println!(
+            "{} machine-filled keys to review are listed in {}",
+            fuzzy.len(),
+            fuzzy_path.display()
+        );
+    }
+    Ok(())
+}
+
+fn run_quiz(direction: QuizDirection, limit: usize, display: chinese::Display) -> Result<()> {
+    let directions = match direction {
+        QuizDirection::ZhEn => vec![Direction::ChineseToEnglish],
+        QuizDirection::EnZh => vec![Direction::EnglishToChinese],
+        QuizDirection::Both => vec![Direction::ChineseToEnglish, Direction::EnglishToChinese],
+    };
+    let mut history = History::open()?;
+    if history.items.is_empty() && !history::is_enabled() {
+        println!("No lookups recorded. Set TRANSLATE_HISTORY=1 to record the words you look up.");
+        return Ok(());
+    }
+    let stdin = std::io::stdin();
+    quiz::run(
+        &mut history,
+        &directions,
+        limit,
+        &mut stdin.lock(),
+        &mut chinese::Writer::new(std::io::stdout(), display),
+    )
+}
+
+fn run_history(clear: bool, display: chinese::Display) -> Result<()> {
+    let mut history = History::open()?;
+    if clear {
+        history.clear();
+        history.save()?;
+        println!("History cleared");
+        return Ok(());
+    }
+    if history.items.is_empty() {
+        println!("No lookups recorded");
+    }
+    for item in &history.items {
+        println!(
+            "{}\t{}\t{} lookups, last {}, next review {}",
+            display.apply(&item.source),
+            display.apply(&item.translation),
+            item.lookups,
+            item.last_lookup,
+            item.zh_en.due.min(item.en_zh.due)
+        );
+    }
+    if !history::is_enabled() {
+        eprintln!("Recording is off. Set TRANSLATE_HISTORY=1 to record lookups.");
+    }
+    Ok(())
+}
 
-    // Perform translation
-    match translate(&dictionary, &args.text, args.ai).await {
-        Ok(translation) => println!("Translation: {}", translation),
-        Err(e) => eprintln!("Error: {}", e),
+fn run_memory_action(action: MemoryAction) -> Result<()> {
+    let mut memory = TranslationMemory::open()?;
+    match action {
+        MemoryAction::Add { source, target } => {
+            memory.add(&source, &target);
+            memory.save()?;
+            println!("Translation memory now has {} segments", memory.len());
+        }
+        MemoryAction::Import { file } => {
+            let xml = fs::read_to_string(&file)?;
+            let imported = memory.import_tmx(&xml)?;
+            memory.save()?;
+            println!(
+                "Imported {} segments, translation memory now has {} segments",
+                imported,
+                memory.len()
+            );
+        }
+        MemoryAction::Export { file } => {
+            fs::write(&file, memory.to_tmx())?;
+            println!("Exported {} segments to {}", memory.len(), file.display());
+        }
     }
+    Ok(())
 }
 
 async fn translate(
-    dictionary: &HashMap<String, String>,
+    dictionary: &Dictionary,
     text: &str,
-    use_ai: bool,
-) -> Result<String, Box<dyn std::error::Error>> {
+    options: &TranslateOptions,
+) -> Result<Entry> {
     // Lookup translation in local dictionary
     // The dictionary is structured as English:Chinese, so we need to search the values for the Chinese text
-    if let Some((english, _)) = dictionary
-        .iter()
-        .find(|(_, chinese)| chinese.contains(text))
-    {
-        return Ok(english.clone());
+    let matches = if options.exact {
+        dictionary.lookup_exact(text, &options.domains)
+    } else {
+        dictionary.lookup(text, &options.domains)
+    };
+    if let Some((best, rest)) = matches.split_first() {
+        let mut entry = Entry::from_dictionary(best.headword, best.gloss);
+        entry.alternatives = rest
+            .iter()
+            .filter(|other| other.gloss == best.gloss)
+            .map(|other| other.headword.to_string())
+            .collect();
+        entry.rare = std::iter::once(&entry.headword)
+            .chain(&entry.alternatives)
+            .filter(|headword| dictionary.is_rare(headword))
+            .cloned()
+            .collect();
+        return Ok(entry);
     }
 
     // If not found and AI flag is set, use AI service
-    if use_ai {
-        println!("Using AI translation service");
-        let ai_service = AITranslationService::new()?;
-        return ai_service.translate(text).await;
+    if options.use_ai {
+        // Status goes to stderr so it can't be mixed into --stdio replies; only with
+        // --verbose, since --filter comes here for every run the dictionaries don't have
+        if options.verbose {
+            eprintln!("Using AI translation service");
+        }
+        let ai_service = match &options.ai_service {
+            Some(service) => service.clone(),
+            None => AITranslationService::new()?,
+        };
+        // Code, URLs, numbers and English words go to the model as markers it copies through
+        let protected = placeholder::protect_mixed(text);
+        if options.chunk_tokens > 0
+            && chunk::estimate_tokens(&protected.text) > options.chunk_tokens
+        {
+            let passage = chunk::translate(
+                &ai_service,
+                &protected,
+                options.chunk_tokens,
+                options.concurrency,
+                options.verify.then_some(options.verify_threshold),
+            )
+            .await?;
+            if options.verbose {
+                print_usage(ai_service.model(), passage.usage);
+            }
+            if passage.lost_markers {
+                eprintln!("{}", LOST_PLACEHOLDER_WARNING);
+            }
+            return Ok(Entry {
+                headword: passage.translation,
+                verification: passage.verification,
+                ..Default::default()
+            });
+        }
+
+        let mut retried = false;
+        let mut entry = loop {
+            let (mut entry, usage) = ai_service.translate(&protected.text).await?;
+            if options.verbose {
+                print_usage(ai_service.model(), usage);
+            }
+            match protected.restore(&entry.headword) {
+                Some(headword) => entry.headword = headword,
+                None if !retried => {
+                    eprintln!("Warning: the translation lost some code, URLs or words; retrying");
+                    retried = true;
+                    continue;
+                }
+                None => {
+                    eprintln!("{}", LOST_PLACEHOLDER_WARNING);
+                    entry.headword = protected.restore_lossy(&entry.headword);
+                }
+            }
+            break entry;
+        };
+        entry.gloss = Some(text.to_string());
+        protected.restore_entry(&mut entry);
+
+        if options.verify {
+            let (back_translation, usage) = ai_service.back_translate(&entry.headword).await?;
+            if options.verbose {
+                print_usage(ai_service.model(), usage);
+            }
+            entry.verification = Some(Verification::new(
+                text,
+                &back_translation,
+                options.verify_threshold,
+            ));
+        }
+        return Ok(entry);
     }
 
     // If not found and AI flag is not set, return an error
-    Err(format!(
-        "Translation not found for '{}'. Try using --ai flag to get AI translation.",
-        text
-    )
-    .into())
+    Err(Error::NotFound(text.to_string()))
 }
 
-fn load_dictionary() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let mut combined_dict = HashMap::new();
+const LOST_PLACEHOLDER_WARNING: &str =
+    "Warning: the translation lost some code, URLs or words of the original; check it before use";
 
-    // Get the dictionary path from environment or use default
-    let dict_path = env::var("TRANSLATE_DICT_PATH").unwrap_or_else(|_| {
-        dirs::home_dir()
-            .map(|home| home.join(".translate/dict"))
-            .and_then(|path| path.to_str().map(|s| s.to_string()))
-            .ok_or("Could not determine dictionary path")
-            .expect("Failed to determine dictionary path")
-    });
-
-    // Read all dictionary files in the dictionary directory
-    for entry in fs::read_dir(&dict_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        // Check if the entry is a file and has a .json extension
-        if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
-            let data = fs::read_to_string(&path)?;
-            let dict: HashMap<String, String> = serde_json::from_str(&data)?;
-
-            // Merge the dictionary into the combined dictionary
-            for (key, value) in dict {
-                combined_dict.insert(key, value);
-            }
-        }
+fn print_usage(model: &str, usage: Option<usage::Usage>) {
+    match usage {
+        Some(usage) => eprintln!(
+            "Model: {}, tokens: {} prompt + {} completion = {}",
+            model,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.total_tokens()
+        ),
+        None => eprintln!("Model: {}, token usage not reported", model),
     }
+}
 
-    Ok(combined_dict)
+/// The translation memory, or an empty one when it can't be read; only commands that
+/// manage the memory fail on it.
+fn open_memory_or_warn() -> TranslationMemory {
+    TranslationMemory::open().unwrap_or_else(|e| {
+        eprintln!("Warning: {}", e);
+        TranslationMemory::default()
+    })
+}
+
+fn load_dictionary() -> Result<Dictionary> {
+    // Get the dictionary path from environment or use default
+    let dict_path = match env::var("TRANSLATE_DICT_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => data_dir()?.join("dict"),
+    };
+
+    // Read all dictionary files in the dictionary directory, ordered by the manifest
+    Dictionary::load(&dict_path)
+}
+
+/// Directory for the tool's local data (dictionaries, usage ledger, price table).
+/// Defaults to `~/.translate` and can be overridden with `TRANSLATE_HOME`.
+fn data_dir() -> Result<PathBuf> {
+    if let Ok(home) = env::var("TRANSLATE_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".translate"))
+        .ok_or_else(|| {
+            Error::Config("Cannot determine the home directory; set TRANSLATE_HOME".to_string())
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use testing::{MockServer, chat_reply, fixture_dictionary};
+
+    fn ai_options(server: &MockServer) -> TranslateOptions {
+        TranslateOptions {
+            use_ai: true,
+            ai_service: Some(server.service()),
+            ..Default::default()
+        }
+    }
 
     #[test]
-    fn test_load_dictionary() {
-        let dictionary = load_dictionary();
-        assert!(dictionary.is_ok());
+    fn test_shared_options_with_subcommands() {
+        let args = Args::try_parse_args(["translate", "-v", "--domain", "team", "usage"]).unwrap();
+        assert!(args.verbose && matches!(args.command, Some(Command::Usage)));
+        assert_eq!(args.domains, vec!["team"]);
 
-        let dict = dictionary.unwrap();
+        for line in [
+            "translate 时钟 usage",
+            "translate --ai usage",
+            "translate --stdio validate",
+        ] {
+            let error = Args::try_parse_args(line.split(' ')).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                clap::error::ErrorKind::ArgumentConflict,
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_coverage_args() {
+        let args = Args::try_parse_args(
+            "translate coverage docs/design.md --domain team --domain-only --top 50 --script traditional"
+                .split(' '),
+        )
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Coverage { top: 50, .. })
+        ));
+        assert_eq!(args.domains, vec!["team"]);
+        assert!(args.domain_only);
+        assert_eq!(args.script, Some(Script::Traditional));
+
+        // --json is shared, so errors are JSON wherever it's given
+        for line in [
+            "translate coverage a.md --json",
+            "translate --json coverage a.md",
+        ] {
+            assert!(
+                Args::try_parse_args(line.split(' ')).unwrap().json,
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_args() {
+        let args = Args::try_parse_args([
+            "translate",
+            "export",
+            "anki",
+            "--from",
+            "dict",
+            "--domain",
+            "computing",
+            "--domain-only",
+            "-o",
+            "computing.apkg",
+            "--deck",
+            "团队::术语",
+        ])
+        .unwrap();
+        let Some(Command::Export {
+            format: ExportFormat::Anki {
+                from, output, deck, ..
+            },
+        }) = args.command
+        else {
+            panic!("expected export anki");
+        };
+        assert!(matches!(from, ExportSource::Dict));
+        assert_eq!(output, PathBuf::from("computing.apkg"));
+        assert_eq!(deck, "团队::术语");
+        assert_eq!(args.domains, vec!["computing"]);
+        assert!(args.domain_only);
+    }
+
+    #[test]
+    fn test_catalog_args() {
+        let args = Args::try_parse_args(
+            "translate --memory-threshold 0.95 catalog po/en.po --ai -v --domain ui".split(' '),
+        )
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Catalog { ai: true, .. })
+        ));
+        assert_eq!(args.memory_threshold, 0.95);
+        assert!(args.verbose);
+        assert_eq!(args.domains, vec!["ui"]);
+    }
+
+    #[test]
+    fn test_load_dictionary() {
+        let dict = fixture_dictionary();
         assert!(dict.contains_key("clock-maker"));
         assert_eq!(
             dict.get("clock-maker"),
@@ -225,38 +1016,197 @@ mod tests {
 
     #[tokio::test]
     async fn test_local_translation() {
-        let dictionary = load_dictionary().unwrap();
-        let result = translate(&dictionary, "制造或修理钟表者", false).await;
-        assert!(result.is_ok());
-        // The dictionary contains multiple entries for "制造或修理钟表者", so we just check that we get one of them
-        let translation = result.unwrap();
-        assert!(translation == "clock-maker" || translation == "clockmaker");
+        let dictionary = fixture_dictionary();
+        let entry = translate(
+            &dictionary,
+            "制造或修理钟表者",
+            &TranslateOptions::default(),
+        )
+        .await
+        .unwrap();
+        // The frequency list ranks the common spelling first and marks the other rare;
+        // README.txt in the fixtures isn't read as a frequency list
+        assert_eq!(entry.headword, "clockmaker");
+        assert_eq!(entry.alternatives, vec!["clock-maker"]);
+        assert_eq!(entry.rare, vec!["clock-maker"]);
+
+        // The manifest ranks the computing dictionary above the base one
+        let entry = translate(&dictionary, "病毒", &TranslateOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(entry.headword, "computer virus");
     }
 
     #[tokio::test]
     async fn test_local_translation_not_found() {
-        let dictionary = load_dictionary().unwrap();
-        let result = translate(&dictionary, "nonexistentword", false).await;
-        assert!(result.is_err());
-        assert!(
-            result
-                .unwrap_err()
-                .to_string()
-                .contains("Translation not found")
+        let dictionary = fixture_dictionary();
+        let result = translate(&dictionary, "nonexistentword", &TranslateOptions::default()).await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::NotFound(_)));
+        assert!(error.to_string().contains("Translation not found"));
+    }
+
+    #[tokio::test]
+    async fn test_ai_translation() {
+        let server = MockServer::start(vec![chat_reply(
+            r#"{"translation": "nonexistent word", "alternatives": ["missing word"], "part_of_speech": "n.", "confidence": 0.8}"#,
+        )])
+        .await;
+        let entry = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap();
+        assert_eq!(entry.headword, "nonexistent word");
+        assert_eq!(entry.alternatives, vec!["missing word"]);
+        assert_eq!(entry.confidence, Some(0.8));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["model"], "mock-model");
+        assert_eq!(requests[0]["messages"][1]["content"], "不存在的词");
+        assert_eq!(requests[0]["response_format"]["type"], "json_object");
+    }
+
+    #[tokio::test]
+    async fn test_ai_translation_keeps_code_and_words() {
+        let text = "请把 `HashMap` 的 key 改成 u64";
+        let server = MockServer::start(vec![
+            chat_reply(r#"{"translation": "Please change the key of ⟦0⟧ to u64"}"#),
+            chat_reply(
+                r#"{"translation": "Please change ⟦1⟧ of ⟦0⟧ to ⟦2⟧", "alternatives": ["Change ⟦0⟧'s key"]}"#,
+            ),
+        ])
+        .await;
+        let entry = translate(&fixture_dictionary(), text, &ai_options(&server))
+            .await
+            .unwrap();
+        assert_eq!(entry.headword, "Please change key of `HashMap` to u64");
+        assert!(entry.alternatives.is_empty());
+        assert_eq!(entry.gloss.as_deref(), Some(text));
+
+        // The first reply lost two markers, so the text was sent again
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0]["messages"][1]["content"],
+            "请把 ⟦0⟧ 的 ⟦1⟧ 改成 ⟦2⟧"
         );
     }
 
-    // Note: This test requires a valid API key in the environment
-    // #[tokio::test]
-    // async fn test_ai_translation() {
-    //     // This test will only pass if you have a valid API key set in your environment
-    //     // Uncomment and run this test only when you have a valid API key
-    //     dotenv().ok(); // Load environment variables for this test
-    //     let dictionary = load_dictionary().unwrap();
-    //     let result = translate(&dictionary, "你好", true).await;
-    //     assert!(result.is_ok());
-    //     // We can't assert the exact translation as it might vary
-    //     // but we can check that it's not empty
-    //     assert!(!result.unwrap().is_empty());
-    // }
+    #[tokio::test]
+    async fn test_dictionary_is_preferred_over_ai() {
+        let server = MockServer::start(Vec::new()).await;
+        let entry = translate(&fixture_dictionary(), "你好", &ai_options(&server))
+            .await
+            .unwrap();
+        assert_eq!(entry.headword, "hello");
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ai_retries_without_json_mode() {
+        let server = MockServer::start(vec![
+            (
+                400,
+                r#"{"error": "response_format is not supported"}"#.to_string(),
+            ),
+            chat_reply("Here is the translation:\n\"nonexistent word\""),
+        ])
+        .await;
+        let entry = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap();
+        assert_eq!(entry.headword, "nonexistent word");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].get("response_format").is_some());
+        assert!(requests[1].get("response_format").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ai_errors() {
+        // Server errors are not retried
+        let server = MockServer::start(vec![(503, "overloaded".to_string())]).await;
+        let error = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ApiStatus { status: 503, .. }));
+        assert_eq!(server.requests().len(), 1);
+
+        // Auth and rate-limit errors aren't caused by JSON mode, so they aren't retried
+        for status in [401, 403, 404, 429] {
+            let server = MockServer::start(vec![(status, "denied".to_string())]).await;
+            let error = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+                .await
+                .unwrap_err();
+            assert!(matches!(error, Error::ApiStatus { status: s, .. } if s == status));
+            assert_eq!(server.requests().len(), 1);
+        }
+
+        // A rejected request that persists without JSON mode is reported after the retry
+        let server = MockServer::start(vec![
+            (422, "invalid".to_string()),
+            (422, "invalid".to_string()),
+        ])
+        .await;
+        let error = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::ApiStatus { status: 422, .. }));
+        assert_eq!(server.requests().len(), 2);
+
+        let server = MockServer::start(vec![(200, "not json".to_string())]).await;
+        let error = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Parse(_)));
+
+        let server = MockServer::start(vec![(200, r#"{"choices": []}"#.to_string())]).await;
+        let error = translate(&fixture_dictionary(), "不存在的词", &ai_options(&server))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Parse(_)));
+
+        // Nothing listens on the discard port
+        let options = TranslateOptions {
+            use_ai: true,
+            ai_service: Some(
+                AITranslationService::with_endpoint(
+                    "http://127.0.0.1:9/v1/",
+                    "test-key",
+                    "mock-model",
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let error = translate(&fixture_dictionary(), "不存在的词", &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Network(_)));
+    }
+
+    #[tokio::test]
+    async fn test_ai_translation_with_verification() {
+        let server = MockServer::start(vec![
+            chat_reply(r#"{"translation": "clockmaker", "confidence": 0.9}"#),
+            chat_reply("钟表匠"),
+        ])
+        .await;
+        let options = TranslateOptions {
+            verify: true,
+            verify_threshold: 0.5,
+            ..ai_options(&server)
+        };
+        let entry = translate(&fixture_dictionary(), "钟表匠", &options)
+            .await
+            .unwrap();
+        let verification = entry.verification.unwrap();
+        assert_eq!(verification.back_translation, "钟表匠");
+        assert!(verification.passed());
+
+        let requests = server.requests();
+        assert_eq!(requests[1]["messages"][1]["content"], "clockmaker");
+        assert!(requests[1].get("response_format").is_none());
+    }
 }
@@ -0,0 +1,297 @@
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default minimum match score for a memory segment to be reused.
+pub const DEFAULT_THRESHOLD: f32 = 0.75;
+
+/// Language codes written to exported TMX files.
+const SOURCE_LANG: &str = "zh-CN";
+const TARGET_LANG: &str = "en";
+
+/// A previously translated pair of segments.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub source: String,
+    pub target: String,
+}
+
+/// How closely the looked-up text matched a stored segment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemoryMatch {
+    /// The stored source segment that matched
+    pub source: String,
+    /// Match score between 0.0 and 1.0, 1.0 being an exact match
+    pub score: f32,
+}
+
+/// Translation memory stored as JSON in `~/.translate/memory.json`.
+#[derive(Debug, Default)]
+pub struct TranslationMemory {
+    path: PathBuf,
+    segments: Vec<Segment>,
+}
+
+impl TranslationMemory {
+    /// Open the memory in the data directory. A missing file is an empty memory.
+    pub fn open() -> Result<Self> {
+        Self::load(&crate::data_dir()?.join("memory.json"))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let segments = if path.exists() {
+            let data = fs::read_to_string(path)?;
+            serde_json::from_str(&data).map_err(|e| {
+                Error::Parse(format!(
+                    "Invalid translation memory {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            segments,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.segments)?)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Add a segment pair, replacing the target of an existing identical source.
+    pub fn add(&mut self, source: &str, target: &str) {
+        let source = source.trim();
+        let target = target.trim();
+        match self.segments.iter_mut().find(|s| s.source == source) {
+            Some(segment) => segment.target = target.to_string(),
+            None => self.segments.push(Segment {
+                source: source.to_string(),
+                target: target.to_string(),
+            }),
+        }
+    }
+
+    /// Find the stored segment closest to `text`, if it scores at least `threshold`.
+    pub fn best_match(&self, text: &str, threshold: f32) -> Option<(&Segment, f32)> {
+        let text: Vec<char> = text.trim().chars().collect();
+        let mut best: Option<(&Segment, f32)> = None;
+        for segment in &self.segments {
+            let source: Vec<char> = segment.source.chars().collect();
+            // Skip segments whose length alone rules out reaching the threshold
+            let longest = text.len().max(source.len()).max(1);
+            let length_bound = 1.0 - text.len().abs_diff(source.len()) as f32 / longest as f32;
+            if length_bound < threshold || best.is_some_and(|(_, score)| length_bound <= score) {
+                continue;
+            }
+            let score = match_score(&text, &source);
+            if score >= threshold && best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((segment, score));
+            }
+        }
+        best
+    }
+
+    /// Look up `text` and turn the best match into an entry.
+    pub fn lookup(&self, text: &str, threshold: f32) -> Option<Entry> {
+        self.best_match(text, threshold)
+            .map(|(segment, score)| Entry {
+                headword: segment.target.clone(),
+                gloss: Some(text.trim().to_string()),
+                memory_match: Some(MemoryMatch {
+                    source: segment.source.clone(),
+                    score,
+                }),
+                ..Default::default()
+            })
+    }
+
+    /// Import the Chinese/English pairs of a TMX document, returning how many were added.
+    pub fn import_tmx(&mut self, xml: &str) -> Result<usize> {
+        let document = roxmltree::Document::parse(xml)?;
+        let mut imported = 0;
+        for unit in document.descendants().filter(|n| n.has_tag_name("tu")) {
+            let mut source = None;
+            let mut target = None;
+            for variant in unit.children().filter(|n| n.has_tag_name("tuv")) {
+                // TMX 1.4 uses xml:lang, older versions use lang
+                let lang = variant
+                    .attribute(("http://www.w3.org/XML/1998/namespace", "lang"))
+                    .or_else(|| variant.attribute("lang"))
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let Some(seg) = variant.children().find(|n| n.has_tag_name("seg")) else {
+                    continue;
+                };
+                let text = segment_text(seg);
+                if lang.starts_with("zh") {
+                    source.get_or_insert(text);
+                } else if lang.starts_with("en") {
+                    target.get_or_insert(text);
+                }
+            }
+            if let (Some(source), Some(target)) = (source, target)
+                && !source.trim().is_empty()
+                && !target.trim().is_empty()
+            {
+                self.add(&source, &target);
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+
+    /// Serialize the memory as a TMX 1.4 document.
+    pub fn to_tmx(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<tmx version=\"1.4\">\n");
+        xml.push_str(&format!(
+            "  <header creationtool=\"translate\" creationtoolversion=\"{}\" segtype=\"sentence\" o-tmf=\"translate\" adminlang=\"en\" srclang=\"{}\" datatype=\"plaintext\"/>\n",
+            env!("CARGO_PKG_VERSION"),
+            SOURCE_LANG
+        ));
+        xml.push_str("  <body>\n");
+        for segment in &self.segments {
+            xml.push_str("    <tu>\n");
+            for (lang, text) in [
+                (SOURCE_LANG, &segment.source),
+                (TARGET_LANG, &segment.target),
+            ] {
+                xml.push_str(&format!(
+                    "      <tuv xml:lang=\"{}\"><seg>{}</seg></tuv>\n",
+                    lang,
+                    escape_xml(text)
+                ));
+            }
+            xml.push_str("    </tu>\n");
+        }
+        xml.push_str("  </body>\n");
+        xml.push_str("</tmx>\n");
+        xml
+    }
+}
+
+/// The text of a `<seg>`, keeping `<hi>` content but dropping inline native codes.
+fn segment_text(node: roxmltree::Node) -> String {
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_text() {
+            text.push_str(child.text().unwrap_or_default());
+        } else if child.is_element()
+            && !matches!(child.tag_name().name(), "bpt" | "ept" | "it" | "ph" | "ut")
+        {
+            text.push_str(&segment_text(child));
+        }
+    }
+    text
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Character-level edit distance turned into a score between 0.0 and 1.0.
+fn match_score(a: &[char], b: &[char]) -> f32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f32 / longest as f32
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> TranslationMemory {
+        let mut memory = TranslationMemory::default();
+        memory.add("请重启服务器。", "Please restart the server.");
+        memory.add("文件已保存。", "The file has been saved.");
+        memory
+    }
+
+    #[test]
+    fn test_exact_and_fuzzy_match() {
+        let memory = memory();
+        let (segment, score) = memory
+            .best_match("请重启服务器。", DEFAULT_THRESHOLD)
+            .unwrap();
+        assert_eq!(segment.target, "Please restart the server.");
+        assert_eq!(score, 1.0);
+
+        // One character differs out of seven
+        let (segment, score) = memory
+            .best_match("请重启服务端。", DEFAULT_THRESHOLD)
+            .unwrap();
+        assert_eq!(segment.source, "请重启服务器。");
+        assert!((score - 6.0 / 7.0).abs() < 1e-6);
+
+        assert!(
+            memory
+                .best_match("今天天气很好", DEFAULT_THRESHOLD)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_tmx_round_trip() {
+        let mut memory = memory();
+        memory.add("a < b & c", "\"quoted\"");
+        let xml = memory.to_tmx();
+
+        let mut imported = TranslationMemory::default();
+        assert_eq!(imported.import_tmx(&xml).unwrap(), 3);
+        assert_eq!(imported.segments, memory.segments);
+    }
+
+    #[test]
+    fn test_import_tmx_with_inline_codes() {
+        let xml = r#"<?xml version="1.0"?>
+<tmx version="1.1"><header srclang="zh-CN"/><body>
+  <tu>
+    <tuv lang="EN-US"><seg>Click <bpt i="1">&lt;b&gt;</bpt>Save<ept i="1">&lt;/b&gt;</ept>.</seg></tuv>
+    <tuv lang="ZH-CN"><seg>点击<hi>保存</hi>。</seg></tuv>
+  </tu>
+  <tu><tuv lang="zh-CN"><seg>没有译文</seg></tuv></tu>
+</body></tmx>"#;
+        let mut memory = TranslationMemory::default();
+        assert_eq!(memory.import_tmx(xml).unwrap(), 1);
+        assert_eq!(
+            memory.segments[0],
+            Segment {
+                source: "点击保存。".to_string(),
+                target: "Click Save.".to_string(),
+            }
+        );
+    }
+}
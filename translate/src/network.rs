@@ -0,0 +1,225 @@
+use crate::error::{Error, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<NetworkConfig> = OnceLock::new();
+
+/// Proxy and TLS settings for the requests to AI providers. A setting left out on the
+/// command line is read from its `TRANSLATE_*` environment variable.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct NetworkConfig {
+    /// Send AI requests through this proxy, e.g. http://proxy.example.com:3128 [env: TRANSLATE_PROXY]
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Proxy credentials; without a password, TRANSLATE_PROXY_PASSWORD is used [env: TRANSLATE_PROXY_USER]
+    #[arg(long, global = true, value_name = "USER[:PASSWORD]")]
+    pub proxy_user: Option<String>,
+
+    /// Comma-separated hosts, domains and IP ranges reached without --proxy [env: TRANSLATE_NO_PROXY]
+    #[arg(long, global = true, value_name = "HOSTS")]
+    pub no_proxy: Option<String>,
+
+    /// Also trust the root certificates of this PEM bundle (repeatable) [env: TRANSLATE_CA_CERT]
+    #[arg(long = "ca-cert", global = true, value_name = "FILE")]
+    pub ca_certs: Vec<PathBuf>,
+
+    /// Present this PEM client certificate, which may include its key [env: TRANSLATE_CLIENT_CERT]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate [env: TRANSLATE_CLIENT_KEY]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub client_key: Option<PathBuf>,
+}
+
+impl NetworkConfig {
+    /// Fill the settings missing from the command line from the environment.
+    pub fn with_env(mut self) -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        self.proxy = self.proxy.or_else(|| var("TRANSLATE_PROXY"));
+        self.proxy_user = self.proxy_user.or_else(|| var("TRANSLATE_PROXY_USER"));
+        self.no_proxy = self.no_proxy.or_else(|| var("TRANSLATE_NO_PROXY"));
+        if self.ca_certs.is_empty()
+            && let Some(paths) = env::var_os("TRANSLATE_CA_CERT")
+        {
+            self.ca_certs = env::split_paths(&paths)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect();
+        }
+        self.client_cert = self
+            .client_cert
+            .or_else(|| var("TRANSLATE_CLIENT_CERT").map(PathBuf::from));
+        self.client_key = self
+            .client_key
+            .or_else(|| var("TRANSLATE_CLIENT_KEY").map(PathBuf::from));
+        self
+    }
+
+    /// Build an HTTP client with these settings. Without a proxy, the usual
+    /// `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` variables still apply.
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(url) = &self.proxy {
+            let mut proxy = reqwest::Proxy::all(url)
+                .map_err(|e| Error::Config(format!("Invalid proxy URL '{}': {}", url, e)))?;
+            if let Some(credentials) = &self.proxy_user {
+                let (user, password) = match credentials.split_once(':') {
+                    Some((user, password)) => (user.to_string(), password.to_string()),
+                    None => (
+                        credentials.clone(),
+                        env::var("TRANSLATE_PROXY_PASSWORD").unwrap_or_default(),
+                    ),
+                };
+                proxy = proxy.basic_auth(&user, &password);
+            }
+            let no_proxy = match &self.no_proxy {
+                Some(hosts) => reqwest::NoProxy::from_string(hosts),
+                None => reqwest::NoProxy::from_env(),
+            };
+            builder = builder.proxy(proxy.no_proxy(no_proxy));
+        }
+
+        for path in &self.ca_certs {
+            let certificates = reqwest::Certificate::from_pem_bundle(&read(path)?)
+                .map_err(|e| invalid_pem(path, e))?;
+            if certificates.is_empty() {
+                return Err(Error::Config(format!(
+                    "No certificates found in {}",
+                    path.display()
+                )));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), key) => {
+                let mut pem = read(cert)?;
+                if let Some(key) = key {
+                    pem.push(b'\n');
+                    pem.extend(read(key)?);
+                }
+                let identity =
+                    reqwest::Identity::from_pem(&pem).map_err(|e| invalid_pem(cert, e))?;
+                builder = builder.identity(identity);
+            }
+            (None, Some(_)) => {
+                return Err(Error::Config(
+                    "A client key was given without a client certificate".to_string(),
+                ));
+            }
+            (None, None) => {}
+        }
+
+        builder
+            .build()
+            .map_err(|e| Error::Config(format!("Failed to set up the HTTP client: {}", e)))
+    }
+}
+
+/// Use `config` for the AI services created from now on.
+pub fn configure(config: NetworkConfig) {
+    let _ = CONFIG.set(config);
+}
+
+/// An HTTP client with the configured settings, or those of the environment when
+/// `configure` wasn't called.
+pub fn client() -> Result<reqwest::Client> {
+    CONFIG
+        .get_or_init(|| NetworkConfig::default().with_env())
+        .client()
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| Error::Config(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn invalid_pem(path: &Path, e: reqwest::Error) -> Error {
+    Error::Config(format!("Invalid PEM file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AITranslationService;
+    use crate::testing::{MockServer, chat_reply};
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let server = MockServer::start(vec![chat_reply(r#"{"translation": "hello"}"#)]).await;
+        let proxied = |no_proxy: Option<&str>| {
+            let config = NetworkConfig {
+                proxy: Some(server.base_url.clone()),
+                proxy_user: Some("alice:secret".to_string()),
+                no_proxy: no_proxy.map(str::to_string),
+                ..Default::default()
+            };
+            // The host doesn't resolve, so requests only arrive through the proxy
+            AITranslationService::with_endpoint("http://api.example.invalid/v1/", "key", "model")
+                .unwrap()
+                .with_client(config.client().unwrap())
+        };
+
+        let (entry, _) = proxied(None).translate("你好").await.unwrap();
+        assert_eq!(entry.headword, "hello");
+        assert_eq!(server.requests().len(), 1);
+        // Basic credentials of alice:secret
+        let headers = &server.headers()[0];
+        assert_eq!(
+            headers.get("proxy-authorization").map(String::as_str),
+            Some("Basic YWxpY2U6c2VjcmV0")
+        );
+        assert_eq!(
+            headers.get("host").map(String::as_str),
+            Some("api.example.invalid")
+        );
+
+        let bypassed = proxied(Some("localhost, .example.invalid"))
+            .translate("你好")
+            .await;
+        assert!(matches!(bypassed, Err(Error::Network(_))));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let dir = std::env::temp_dir().join(format!("translate-network-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let not_pem = dir.join("not.pem");
+        fs::write(&not_pem, "not a certificate").unwrap();
+
+        for config in [
+            NetworkConfig {
+                proxy: Some("not a url".to_string()),
+                ..Default::default()
+            },
+            NetworkConfig {
+                ca_certs: vec![not_pem.clone()],
+                ..Default::default()
+            },
+            NetworkConfig {
+                ca_certs: vec![dir.join("missing.pem")],
+                ..Default::default()
+            },
+            NetworkConfig {
+                client_cert: Some(not_pem.clone()),
+                ..Default::default()
+            },
+            NetworkConfig {
+                client_key: Some(not_pem.clone()),
+                ..Default::default()
+            },
+        ] {
+            assert!(
+                matches!(config.client(), Err(Error::Config(_))),
+                "{:?}",
+                config
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
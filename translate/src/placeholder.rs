@@ -0,0 +1,292 @@
+use crate::chinese::is_han;
+use crate::entry::Entry;
+use std::ops::Range;
+
+/// Text whose placeholders have been swapped for numbered markers such as `⟦0⟧`,
+/// which models copy through unchanged, so a translation can't break them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Protected {
+    pub text: String,
+    originals: Vec<String>,
+}
+
+impl Protected {
+    /// Put the original placeholders back into a translation of `self.text`.
+    /// Returns `None` when a marker was lost or duplicated.
+    pub fn restore(&self, translation: &str) -> Option<String> {
+        let mut restored = translation.to_string();
+        for (i, original) in self.originals.iter().enumerate() {
+            let marker = marker(i);
+            if restored.matches(&marker).count() != 1 {
+                return None;
+            }
+            restored = restored.replace(&marker, original);
+        }
+        Some(restored)
+    }
+
+    /// Whether the translation of `part`, a piece of `self.text`, has each of its
+    /// markers exactly once.
+    pub fn keeps_markers(&self, part: &str, translation: &str) -> bool {
+        (0..self.originals.len())
+            .map(marker)
+            .filter(|marker| part.contains(marker))
+            .all(|marker| translation.matches(&marker).count() == 1)
+    }
+
+    /// Put back the placeholders whose markers survived, leaving out the lost ones.
+    pub fn restore_lossy(&self, translation: &str) -> String {
+        let mut restored = translation.to_string();
+        for (i, original) in self.originals.iter().enumerate() {
+            restored = restored.replace(&marker(i), original);
+        }
+        restored
+    }
+
+    /// Restore the alternatives and example of an AI entry; an alternative that lost a
+    /// marker is left out.
+    pub fn restore_entry(&self, entry: &mut Entry) {
+        entry.alternatives = entry
+            .alternatives
+            .iter()
+            .filter_map(|alternative| self.restore(alternative))
+            .collect();
+        entry.example = entry
+            .example
+            .as_deref()
+            .map(|example| self.restore_lossy(example));
+    }
+}
+
+fn marker(i: usize) -> String {
+    format!("⟦{}⟧", i)
+}
+
+/// Replace format placeholders: printf style (`%s`, `%1$d`, `%(name)s`, `%.2f`),
+/// braces (`{name}`, `{0}`) and double braces (`{{count}}`).
+pub fn protect(text: &str) -> Protected {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some(offset) = text[start..].find(['%', '{']) {
+        let candidate = start + offset;
+        match placeholder_len(&text[candidate..]) {
+            Some(len) => {
+                spans.push(candidate..candidate + len);
+                start = candidate + len;
+            }
+            None => start = candidate + 1,
+        }
+    }
+    protect_spans(text, &spans)
+}
+
+/// Replace the given byte ranges of `text`, which must be in order and not overlap.
+pub fn protect_spans(text: &str, spans: &[Range<usize>]) -> Protected {
+    let mut protected = String::new();
+    let mut originals = Vec::new();
+    let mut end = 0;
+    for span in spans {
+        protected.push_str(&text[end..span.start]);
+        protected.push_str(&marker(originals.len()));
+        originals.push(text[span.clone()].to_string());
+        end = span.end;
+    }
+    protected.push_str(&text[end..]);
+    Protected {
+        text: protected,
+        originals,
+    }
+}
+
+/// Replace everything in Chinese text that should come through a translation unchanged:
+/// `code` and fenced code, URLs, format placeholders, and runs of Latin words,
+/// identifiers and numbers such as `HashMap`, `std::fs`, `v1.2` or `50%`.
+/// Text without Chinese is left as it is.
+pub fn protect_mixed(text: &str) -> Protected {
+    if !text.chars().any(is_han) {
+        return protect_spans(text, &[]);
+    }
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let rest = &text[start..];
+        let len = if let Some(fenced) = rest.strip_prefix("```") {
+            fenced.find("```").map(|end| end + 6)
+        } else if let Some(inline) = rest.strip_prefix('`') {
+            inline.find('`').map(|end| end + 2)
+        } else if rest.starts_with(['%', '{']) {
+            placeholder_len(rest)
+        } else if ["http://", "https://", "ftp://", "www."]
+            .iter()
+            .any(|scheme| rest.starts_with(scheme))
+        {
+            Some(url_len(rest))
+        } else {
+            latin_len(rest)
+        };
+        match len {
+            Some(len) => {
+                // Latin words separated by a space are one span, e.g. "Pull Request"
+                match spans.last_mut() {
+                    Some(last)
+                        if text[last.end..start] == *" "
+                            && is_latin_span(&text[last.clone()])
+                            && is_latin_span(&rest[..len]) =>
+                    {
+                        last.end = start + len
+                    }
+                    _ => spans.push(start..start + len),
+                }
+                start += len;
+            }
+            None => start += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    protect_spans(text, &spans)
+}
+
+fn is_latin_span(span: &str) -> bool {
+    span.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+/// A URL runs until whitespace or Chinese, without trailing punctuation.
+fn url_len(text: &str) -> usize {
+    let end = text
+        .find(|c: char| c.is_whitespace() || is_han(c) || !c.is_ascii())
+        .unwrap_or(text.len());
+    text[..end]
+        .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '\'', '"'])
+        .len()
+}
+
+/// A Latin word, identifier or number: ASCII letters, digits and `_`, joined by
+/// `.`, `:`, `-`, `/`, `'`, `@` or `,` (between digits), ending in `++`, `#` or `%` if any.
+fn latin_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    if !bytes.first().is_some_and(|&b| word(b)) {
+        return None;
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        let joins = word(b)
+            || (b"._:-/'@".contains(&b) && next.is_some_and(word))
+            || (b == b','
+                && bytes[i - 1].is_ascii_digit()
+                && next.is_some_and(|n| n.is_ascii_digit()));
+        if joins {
+            i += 1;
+        } else if b == b':' && next == Some(b':') && bytes.get(i + 2).is_some_and(|&n| word(n)) {
+            i += 2;
+        } else {
+            break;
+        }
+    }
+    let suffix = &text[i..];
+    if suffix.starts_with("++") {
+        i += 2;
+    } else if suffix.starts_with('#') || (suffix.starts_with('%') && bytes[i - 1].is_ascii_digit())
+    {
+        i += 1;
+    }
+    Some(i)
+}
+
+/// Length of the placeholder at the start of `text`, if there is one.
+fn placeholder_len(text: &str) -> Option<usize> {
+    if let Some(inner) = text.strip_prefix("{{") {
+        let end = inner.find("}}")?;
+        return is_name(inner[..end].trim()).then_some(end + 4);
+    }
+    if let Some(inner) = text.strip_prefix('{') {
+        let end = inner.find('}')?;
+        return is_name(&inner[..end]).then_some(end + 2);
+    }
+
+    // %[position$ | (name)][flags][width][.precision]conversion
+    let bytes = text.as_bytes();
+    let mut i = 1;
+    if bytes.get(i) == Some(&b'(') {
+        i += text[i..].find(')')? + 1;
+    } else {
+        let digits = text[i..].bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 && bytes.get(i + digits) == Some(&b'$') {
+            i += digits + 1;
+        }
+    }
+    i += text[i..]
+        .bytes()
+        .take_while(|b| b"-+ 0#".contains(b))
+        .count();
+    i += text[i..].bytes().take_while(u8::is_ascii_digit).count();
+    if bytes.get(i) == Some(&b'.') {
+        i += 1 + text[i + 1..].bytes().take_while(u8::is_ascii_digit).count();
+    }
+    let conversion = *bytes.get(i)?;
+    b"sdifFeEgGxXoucpq@%".contains(&conversion).then_some(i + 1)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protect_and_restore() {
+        let protected =
+            protect("%s 删除了 {count} 个文件，剩余 {{ total }}（%1$d、%(name)s、%.2f%%）");
+        assert_eq!(
+            protected.text,
+            "⟦0⟧ 删除了 ⟦1⟧ 个文件，剩余 ⟦2⟧（⟦3⟧、⟦4⟧、⟦5⟧⟦6⟧）"
+        );
+        assert_eq!(
+            protected.restore("⟦0⟧ deleted ⟦1⟧ files, ⟦2⟧ left (⟦3⟧, ⟦4⟧, ⟦5⟧⟦6⟧)"),
+            Some("%s deleted {count} files, {{ total }} left (%1$d, %(name)s, %.2f%%)".to_string())
+        );
+        assert_eq!(protected.restore("deleted files"), None);
+
+        // Braces that aren't placeholders and a lone percent sign are left alone
+        let protected = protect("100% {不是 占位符}");
+        assert!(protected.originals.is_empty());
+        assert_eq!(protected.text, "100% {不是 占位符}");
+    }
+
+    #[test]
+    fn test_protect_mixed() {
+        let text = "请把 `HashMap` 的 key 改成 u64，详见 https://doc.rust-lang.org/std/（第 3.5 节）。Pull Request 通过率 95%，C++ 和 std::fs 不变";
+        let protected = protect_mixed(text);
+        assert_eq!(
+            protected.text,
+            "请把 ⟦0⟧ 的 ⟦1⟧ 改成 ⟦2⟧，详见 ⟦3⟧（第 ⟦4⟧ 节）。⟦5⟧ 通过率 ⟦6⟧，⟦7⟧ 和 ⟦8⟧ 不变"
+        );
+        assert_eq!(
+            protected.originals,
+            vec![
+                "`HashMap`",
+                "key",
+                "u64",
+                "https://doc.rust-lang.org/std/",
+                "3.5",
+                "Pull Request",
+                "95%",
+                "C++",
+                "std::fs"
+            ]
+        );
+        assert_eq!(
+            protected.restore_lossy("Change the ⟦1⟧ of ⟦0⟧ to ⟦2⟧"),
+            "Change the key of `HashMap` to u64"
+        );
+
+        // Nothing to translate around English alone
+        assert!(protect_mixed("HashMap key").originals.is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::error::Result;
+use crate::history::{Direction, History};
+use chrono::{Local, NaiveDate};
+use std::io::{BufRead, Write};
+
+/// Run a flashcard session over the due cards, saving progress after every answer.
+pub fn run(
+    history: &mut History,
+    directions: &[Direction],
+    limit: usize,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<()> {
+    run_on(
+        history,
+        directions,
+        limit,
+        Local::now().date_naive(),
+        input,
+        output,
+        |history| history.save(),
+    )
+}
+
+fn run_on(
+    history: &mut History,
+    directions: &[Direction],
+    limit: usize,
+    today: NaiveDate,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    mut save: impl FnMut(&History) -> Result<()>,
+) -> Result<()> {
+    let due = history.due(directions, today);
+    if due.is_empty() {
+        writeln!(output, "Nothing to review today.")?;
+        return Ok(());
+    }
+
+    let total = due.len().min(limit);
+    let mut reviewed = 0;
+    for (n, (index, direction)) in due.into_iter().take(limit).enumerate() {
+        let item = &history.items[index];
+        let (prompt, answer) = match direction {
+            Direction::ChineseToEnglish => (&item.source, &item.translation),
+            Direction::EnglishToChinese => (&item.translation, &item.source),
+        };
+        writeln!(output, "[{}/{}] {}", n + 1, total, prompt)?;
+        write!(output, "Press Enter to show the answer (q to quit) ")?;
+        output.flush()?;
+        if read_line(input)?.is_none_or(|line| line == "q") {
+            writeln!(output)?;
+            break;
+        }
+
+        writeln!(output, "Answer: {}", answer)?;
+        let Some(quality) = ask_quality(input, output)? else {
+            writeln!(output)?;
+            break;
+        };
+        let card = history.items[index].card_mut(direction);
+        card.review(quality, today);
+        let due = card.due;
+        save(history)?;
+        reviewed += 1;
+        writeln!(output, "Next review: {}\n", due)?;
+    }
+
+    writeln!(output, "Reviewed {} of {} cards.", reviewed, total)?;
+    Ok(())
+}
+
+/// Ask for an SM-2 grade until a valid one is given. `None` means the user quit.
+fn ask_quality(input: &mut impl BufRead, output: &mut impl Write) -> Result<Option<u8>> {
+    loop {
+        write!(
+            output,
+            "How well did you remember? 0 (forgot) - 5 (perfect), q to quit: "
+        )?;
+        output.flush()?;
+        match read_line(input)? {
+            None => return Ok(None),
+            Some(line) if line == "q" => return Ok(None),
+            Some(line) => {
+                if let Ok(quality @ 0..=5) = line.parse::<u8>() {
+                    return Ok(Some(quality));
+                }
+            }
+        }
+    }
+}
+
+fn read_line(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiz_session() {
+        let today = NaiveDate::from_ymd_opt(2025, 9, 1).unwrap();
+        let mut history = History::default();
+        history.record("钟表匠", "clockmaker", today);
+
+        let mut input = "\n7\n4\n\n0\n".as_bytes();
+        let mut output = Vec::new();
+        let mut saves = 0;
+        run_on(
+            &mut history,
+            &[Direction::ChineseToEnglish, Direction::EnglishToChinese],
+            10,
+            today,
+            &mut input,
+            &mut output,
+            |_| {
+                saves += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("[1/2] 钟表匠"));
+        assert!(output.contains("Answer: clockmaker"));
+        assert!(output.contains("[2/2] clockmaker"));
+        assert!(output.contains("Reviewed 2 of 2 cards."));
+        assert_eq!(saves, 2);
+        assert_eq!(history.items[0].zh_en.interval_days, 1);
+        assert_eq!(history.items[0].en_zh.repetitions, 0);
+    }
+}
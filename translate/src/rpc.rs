@@ -0,0 +1,448 @@
+use crate::chinese;
+use crate::dictionary::{Dictionary, DomainFilter};
+use crate::entry::Entry;
+use crate::error::{self, Error};
+use crate::memory::TranslationMemory;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::{AbortHandle, JoinHandle, LocalSet};
+
+/// Error codes defined by JSON-RPC 2.0.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The method ran but failed, e.g. no translation was found or the AI request failed.
+pub const TRANSLATION_FAILED: i64 = -32000;
+/// Reply to a request that was cancelled, the same code as the Language Server Protocol uses.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+    /// The kind of a failed translation, as in the `--json` error output
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        Self {
+            code: TRANSLATION_FAILED,
+            message: e.to_string(),
+            data: Some(json!({ "kind": e.kind() })),
+        }
+    }
+}
+
+/// Parameters of `lookup`, `translate` and `identifier`.
+#[derive(Debug, Deserialize)]
+struct TextParams {
+    text: String,
+    /// Domain tags for this request, replacing the ones given on the command line
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    domain_only: bool,
+    /// Fall back to the AI service, on by default for `translate` and `identifier`
+    ai: Option<bool>,
+    #[serde(default)]
+    verify: bool,
+    /// Naming convention of the identifier
+    #[serde(default)]
+    case: IdentifierCase,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+/// Naming conventions for `identifier`.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentifierCase {
+    /// userName
+    #[default]
+    Camel,
+    /// UserName
+    Pascal,
+    /// user_name
+    Snake,
+    /// user-name
+    Kebab,
+    /// USER_NAME
+    Constant,
+}
+
+/// Turn an English translation into an identifier, dropping articles and punctuation.
+pub fn identifier(english: &str, case: IdentifierCase) -> String {
+    let mut words: Vec<String> = english
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect();
+    if words.len() > 1 {
+        words.retain(|word| !matches!(word.as_str(), "a" | "an" | "the"));
+    }
+
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars
+            .next()
+            .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+            .unwrap_or_default()
+    };
+    let name = match case {
+        IdentifierCase::Camel => words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                if i == 0 {
+                    word.clone()
+                } else {
+                    capitalize(word)
+                }
+            })
+            .collect(),
+        IdentifierCase::Pascal => words.iter().map(|word| capitalize(word)).collect(),
+        IdentifierCase::Snake => words.join("_"),
+        IdentifierCase::Kebab => words.join("-"),
+        IdentifierCase::Constant => words.join("_").to_ascii_uppercase(),
+    };
+    // Identifiers can't start with a digit
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// The long-lived backend behind `translate --stdio`.
+///
+/// Every request runs as its own task, so a slow AI call doesn't hold up the dictionary
+/// lookups sent after it, and replies are written as soon as they are ready, in any order.
+pub struct Server {
+    dictionary: Dictionary,
+    memory: TranslationMemory,
+    defaults: crate::TranslateOptions,
+    memory_threshold: f32,
+    /// Requests still running, keyed by their JSON-encoded id
+    pending: RefCell<HashMap<String, AbortHandle>>,
+    output: Box<dyn Fn(&Value)>,
+}
+
+impl Server {
+    pub fn new(
+        dictionary: Dictionary,
+        memory: TranslationMemory,
+        defaults: crate::TranslateOptions,
+        memory_threshold: f32,
+    ) -> Self {
+        Self {
+            dictionary,
+            memory,
+            defaults,
+            memory_threshold,
+            pending: RefCell::new(HashMap::new()),
+            output: Box::new(|message| {
+                let mut stdout = std::io::stdout().lock();
+                let _ = writeln!(stdout, "{}", message).and_then(|_| stdout.flush());
+            }),
+        }
+    }
+
+    /// Serve line-delimited JSON-RPC on stdin and stdout until stdin is closed,
+    /// then wait for the requests still running.
+    pub async fn serve(self) -> error::Result<()> {
+        let server = Rc::new(self);
+        LocalSet::new()
+            .run_until(async move {
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+                let mut tasks = Vec::new();
+                while let Some(line) = lines.next_line().await? {
+                    tasks.retain(|task: &JoinHandle<()>| !task.is_finished());
+                    tasks.extend(Rc::clone(&server).handle_line(&line));
+                }
+                for task in tasks {
+                    // Cancelled tasks end with a JoinError, which needs no reply
+                    let _ = task.await;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Handle one line of input, returning the task running the request if one was started.
+    /// Must be called from within a `LocalSet`.
+    fn handle_line(self: Rc<Self>, line: &str) -> Option<JoinHandle<()>> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(e) => {
+                self.reply(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())));
+                return None;
+            }
+        };
+
+        // Requests without an id are notifications and never get a reply
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            self.reply(
+                id.unwrap_or(Value::Null),
+                Err(RpcError::new(INVALID_REQUEST, "Missing method")),
+            );
+            return None;
+        };
+        let method = method.to_string();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        if method == "cancel" {
+            let result = self.cancel(params);
+            if let Some(id) = id {
+                self.reply(id, result);
+            }
+            return None;
+        }
+
+        let key = id.as_ref().map(Value::to_string);
+        if let Some(key) = &key
+            && self.pending.borrow().contains_key(key)
+        {
+            self.reply(
+                id.unwrap_or(Value::Null),
+                Err(RpcError::new(INVALID_REQUEST, "Duplicate request id")),
+            );
+            return None;
+        }
+
+        let server = Rc::clone(&self);
+        let task = tokio::task::spawn_local(async move {
+            let result = server.call(&method, params).await;
+            if let Some(key) = &key {
+                server.pending.borrow_mut().remove(key);
+            }
+            if let Some(id) = id {
+                server.reply(id, result);
+            }
+        });
+        // The task can't have started yet, so it is registered before it could finish
+        if let Some(key) = message.get("id").map(Value::to_string) {
+            self.pending.borrow_mut().insert(key, task.abort_handle());
+        }
+        Some(task)
+    }
+
+    /// Abort a running request and answer it with `REQUEST_CANCELLED`.
+    fn cancel(&self, params: Value) -> Result<Value, RpcError> {
+        let params: CancelParams = serde_json::from_value(params)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+        let handle = self.pending.borrow_mut().remove(&params.id.to_string());
+        let cancelled = handle.is_some();
+        if let Some(handle) = handle {
+            handle.abort();
+            self.reply(
+                params.id,
+                Err(RpcError::new(REQUEST_CANCELLED, "Request cancelled")),
+            );
+        }
+        Ok(json!({ "cancelled": cancelled }))
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let parse = |params: Value| -> Result<TextParams, RpcError> {
+            serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+        };
+        match method {
+            "lookup" => {
+                let params = parse(params)?;
+                let entry = self.translate(&params, false).await?;
+                Ok(serde_json::to_value(entry).unwrap_or_default())
+            }
+            "translate" => {
+                let params = parse(params)?;
+                let entry = self.translate(&params, params.ai.unwrap_or(true)).await?;
+                Ok(serde_json::to_value(entry).unwrap_or_default())
+            }
+            "identifier" => {
+                let params = parse(params)?;
+                let entry = self.translate(&params, params.ai.unwrap_or(true)).await?;
+                Ok(json!({
+                    "identifier": identifier(&entry.headword, params.case),
+                    "entry": entry,
+                }))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'", method),
+            )),
+        }
+    }
+
+    /// The same lookup order as the command line: memory, dictionary, then the AI service.
+    async fn translate(&self, params: &TextParams, use_ai: bool) -> Result<Entry, RpcError> {
+        let text = chinese::normalize_input(&params.text);
+        if let Some(entry) = self.memory.lookup(&text, self.memory_threshold) {
+            return Ok(entry);
+        }
+
+        let mut options = crate::TranslateOptions {
+            use_ai,
+            verify: params.verify,
+            ..self.defaults.clone()
+        };
+        if !params.domains.is_empty() {
+            options.domains = DomainFilter {
+                tags: params.domains.clone(),
+                restrict: params.domain_only,
+            };
+        }
+        Ok(crate::translate(&self.dictionary, &text, &options).await?)
+    }
+
+    fn reply(&self, id: Value, result: Result<Value, RpcError>) {
+        let message = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => {
+                let mut body = json!({ "code": error.code, "message": error.message });
+                if let Some(data) = error.data {
+                    body["data"] = data;
+                }
+                json!({ "jsonrpc": "2.0", "id": id, "error": body })
+            }
+        };
+        (self.output)(&message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Source;
+
+    fn server() -> (Rc<Server>, Rc<RefCell<Vec<Value>>>) {
+        let mut dictionary = Dictionary::default();
+        dictionary.add_source(
+            Source {
+                name: "base.json".to_string(),
+                priority: 0,
+                domains: Vec::new(),
+            },
+            HashMap::from([
+                ("user name".to_string(), "n. 用户名".to_string()),
+                ("clock".to_string(), "n. 钟".to_string()),
+            ]),
+        );
+        let replies = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&replies);
+        let server = Server {
+            output: Box::new(move |message| sink.borrow_mut().push(message.clone())),
+            ..Server::new(
+                dictionary,
+                TranslationMemory::default(),
+                crate::TranslateOptions::default(),
+                1.0,
+            )
+        };
+        (Rc::new(server), replies)
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("user name", IdentifierCase::Camel), "userName");
+        assert_eq!(
+            identifier("clock-maker", IdentifierCase::Pascal),
+            "ClockMaker"
+        );
+        assert_eq!(
+            identifier("Restart the server.", IdentifierCase::Snake),
+            "restart_server"
+        );
+        assert_eq!(
+            identifier("max retries", IdentifierCase::Constant),
+            "MAX_RETRIES"
+        );
+        assert_eq!(identifier("3D model", IdentifierCase::Kebab), "_3d-model");
+    }
+
+    #[tokio::test]
+    async fn test_requests() {
+        let (server, replies) = server();
+        LocalSet::new()
+            .run_until(async {
+                let lines = [
+                    r#"{"jsonrpc":"2.0","id":1,"method":"lookup","params":{"text":"钟"}}"#,
+                    r#"{"jsonrpc":"2.0","id":"b","method":"identifier","params":{"text":"用戶名","case":"snake","ai":false}}"#,
+                    r#"{"jsonrpc":"2.0","id":3,"method":"lookup","params":{"text":"电脑"}}"#,
+                    r#"{"jsonrpc":"2.0","id":4,"method":"define"}"#,
+                    r#"{"jsonrpc":"2.0","id":5,"method":"lookup","params":{}}"#,
+                    r#"{"jsonrpc":"2.0","id":6,"method":"cancel","params":{"id":99}}"#,
+                    r#"{"jsonrpc":"2.0","method":"lookup","params":{"text":"钟"}}"#,
+                    "not json",
+                ];
+                let tasks: Vec<_> = lines
+                    .iter()
+                    .filter_map(|line| Rc::clone(&server).handle_line(line))
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+            .await;
+
+        let replies = replies.borrow();
+        let reply = |id: Value| replies.iter().find(|r| r["id"] == id).unwrap().clone();
+        assert_eq!(reply(json!(1))["result"]["headword"], "clock");
+        assert_eq!(reply(json!("b"))["result"]["identifier"], "user_name");
+        assert_eq!(reply(json!(3))["error"]["code"], TRANSLATION_FAILED);
+        assert_eq!(reply(json!(3))["error"]["data"]["kind"], "not_found");
+        assert_eq!(reply(json!(4))["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(reply(json!(5))["error"]["code"], INVALID_PARAMS);
+        assert_eq!(reply(json!(6))["result"]["cancelled"], false);
+        assert_eq!(reply(Value::Null)["error"]["code"], PARSE_ERROR);
+        // The notification got no reply
+        assert_eq!(replies.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let (server, replies) = server();
+        LocalSet::new()
+            .run_until(async {
+                // Stands in for a slow AI request
+                let slow = tokio::task::spawn_local(std::future::pending::<()>());
+                server
+                    .pending
+                    .borrow_mut()
+                    .insert(json!(7).to_string(), slow.abort_handle());
+
+                let cancel = r#"{"jsonrpc":"2.0","id":8,"method":"cancel","params":{"id":7}}"#;
+                assert!(Rc::clone(&server).handle_line(cancel).is_none());
+                assert!(slow.await.unwrap_err().is_cancelled());
+            })
+            .await;
+
+        let replies = replies.borrow();
+        assert_eq!(replies[0]["id"], 7);
+        assert_eq!(replies[0]["error"]["code"], REQUEST_CANCELLED);
+        assert_eq!(replies[1]["result"]["cancelled"], true);
+        assert!(server.pending.borrow().is_empty());
+    }
+}
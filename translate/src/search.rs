@@ -0,0 +1,206 @@
+use crate::chinese::is_han;
+use crate::dictionary::Dictionary;
+use crate::entry::split_part_of_speech;
+use std::collections::HashMap;
+
+/// BM25 parameters, the usual defaults.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// A headword found by meaning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit<'a> {
+    pub headword: &'a str,
+    pub gloss: &'a str,
+    pub score: f32,
+}
+
+/// Rank dictionary entries by how well their glosses match `query`, e.g. "修理 钟",
+/// using BM25 over the characters and character bigrams of each gloss.
+///
+/// Entries containing more of the query's words are ranked first, so "修理 钟" prefers
+/// a gloss with both words over one that only repeats 修理.
+///
+/// A query term with `*` or `?` is also a wildcard (e.g. `钟*者`): entries whose gloss
+/// has nothing matching it are left out.
+pub fn search<'a>(dictionary: &'a Dictionary, query: &str, limit: usize) -> Vec<Hit<'a>> {
+    let wildcards: Vec<&str> = query
+        .split_whitespace()
+        .filter(|term| term.contains(['*', '?']))
+        .collect();
+    let literal = query.replace(['*', '?'], " ");
+    let words: Vec<&str> = literal.split_whitespace().collect();
+    let query_terms = terms(&literal);
+    if query_terms.is_empty() {
+        return Vec::new();
+    }
+
+    let documents: Vec<(&str, &str, HashMap<String, u32>, usize)> = dictionary
+        .iter()
+        .map(|(headword, gloss)| {
+            let grams = terms(&split_part_of_speech(gloss).1);
+            let length = grams.len();
+            let mut frequencies = HashMap::new();
+            for gram in grams {
+                *frequencies.entry(gram).or_insert(0) += 1;
+            }
+            (headword, gloss, frequencies, length)
+        })
+        .collect();
+    if documents.is_empty() {
+        return Vec::new();
+    }
+
+    let count = documents.len() as f32;
+    let average_length = documents.iter().map(|(.., length)| *length).sum::<usize>() as f32 / count;
+    let mut document_frequency: HashMap<&str, u32> = HashMap::new();
+    for term in &query_terms {
+        if document_frequency.contains_key(term.as_str()) {
+            continue;
+        }
+        let frequency = documents
+            .iter()
+            .filter(|(_, _, frequencies, _)| frequencies.contains_key(term))
+            .count() as u32;
+        document_frequency.insert(term, frequency);
+    }
+
+    let mut hits: Vec<Hit> = documents
+        .iter()
+        .filter(|(_, gloss, ..)| {
+            wildcards
+                .iter()
+                .all(|pattern| matches_anywhere(pattern, gloss))
+        })
+        .filter_map(|(headword, gloss, frequencies, length)| {
+            let score: f32 = query_terms
+                .iter()
+                .map(|term| {
+                    let tf = *frequencies.get(term).unwrap_or(&0) as f32;
+                    if tf == 0.0 {
+                        return 0.0;
+                    }
+                    let df = document_frequency[term.as_str()] as f32;
+                    let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    let norm = 1.0 - B + B * *length as f32 / average_length.max(1.0);
+                    idf * tf * (K1 + 1.0) / (tf + K1 * norm)
+                })
+                .sum();
+            let matched = words.iter().filter(|word| gloss.contains(*word)).count();
+            let coverage = (1 + matched) as f32 / (1 + words.len()) as f32;
+            let score = score * coverage * coverage;
+            (score > 0.0).then_some(Hit {
+                headword,
+                gloss,
+                score,
+            })
+        })
+        .collect();
+
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(dictionary.frequency_order(a.headword, b.headword))
+            .then(a.headword.len().cmp(&b.headword.len()))
+            .then(a.headword.cmp(b.headword))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+/// Index terms of a text: each Chinese character and each pair of neighbouring
+/// characters, plus lowercased words of any other script.
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut word = String::new();
+    for c in text.chars() {
+        if is_han(c) {
+            terms.push(c.to_string());
+            if let Some(previous) = previous {
+                terms.push(format!("{}{}", previous, c));
+            }
+            previous = Some(c);
+        } else {
+            previous = None;
+        }
+
+        if c.is_alphanumeric() && !is_han(c) {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            terms.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        terms.push(word);
+    }
+    terms
+}
+
+/// Whether `pattern`, with `*` for any run of characters and `?` for one character,
+/// matches some part of `text`.
+fn matches_anywhere(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    (0..=text.len()).any(|start| matches_prefix(&pattern, &text[start..]))
+}
+
+fn matches_prefix(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some(('*', rest)) => (0..=text.len()).any(|skip| matches_prefix(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && matches_prefix(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && matches_prefix(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Source;
+
+    fn sample() -> Dictionary {
+        let mut dictionary = Dictionary::default();
+        dictionary.add_source(
+            Source {
+                name: "base.json".to_string(),
+                priority: 0,
+                domains: Vec::new(),
+            },
+            [
+                ("clock-maker", "n. 制造或修理钟表者"),
+                ("clock", "n. 时钟；钟"),
+                ("repair", "v. 修理；修补"),
+                ("bell", "n. 钟；铃"),
+                ("computer", "n. 计算机；电脑"),
+                ("API", "n. 应用程序接口 (API)"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        );
+        dictionary
+    }
+
+    #[test]
+    fn test_search_ranks_by_meaning() {
+        let dictionary = sample();
+        let hits = search(&dictionary, "修理 钟", 3);
+        assert_eq!(hits[0].headword, "clock-maker");
+        assert_eq!(hits.len(), 3);
+        assert!(hits.iter().all(|hit| hit.headword != "computer"));
+
+        assert_eq!(search(&dictionary, "api", 5)[0].headword, "API");
+        assert!(search(&dictionary, "  ", 5).is_empty());
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let dictionary = sample();
+        let hits = search(&dictionary, "钟*者", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].headword, "clock-maker");
+        assert!(matches_anywhere("时?", "n. 时钟；钟"));
+        assert!(!matches_anywhere("钟??者", "钟表者"));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::ai::AITranslationService;
+use crate::chinese::is_han;
+use crate::error::Result;
+
+/// A subtitle file, kept close enough to the original that writing it back
+/// reproduces everything but the translated cue text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subtitles {
+    blocks: Vec<Block>,
+    newline: &'static str,
+    bom: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Cue(Cue),
+    /// The WebVTT header, NOTE, STYLE and REGION blocks, copied as they are
+    Other(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    /// The SRT number or WebVTT identifier, and the timing line with its settings
+    header: Vec<String>,
+    text: Vec<String>,
+}
+
+/// Parse SRT or WebVTT. Both are blocks separated by blank lines, with cues
+/// recognized by their `-->` timing line.
+pub fn parse(content: &str) -> Subtitles {
+    let bom = content.starts_with('\u{feff}');
+    let content = content.trim_start_matches('\u{feff}');
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    let mut blocks = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            lines.push(line.to_string());
+            continue;
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        let lines = std::mem::take(&mut lines);
+        let is_other = ["WEBVTT", "NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|keyword| lines[0].starts_with(keyword));
+        match lines.iter().position(|line| line.contains("-->")) {
+            Some(timing) if !is_other => {
+                let (header, text) = lines.split_at(timing + 1);
+                blocks.push(Block::Cue(Cue {
+                    header: header.to_vec(),
+                    text: text.to_vec(),
+                }));
+            }
+            _ => blocks.push(Block::Other(lines)),
+        }
+    }
+    Subtitles {
+        blocks,
+        newline,
+        bom,
+    }
+}
+
+impl Subtitles {
+    fn cues_mut(&mut self) -> impl Iterator<Item = &mut Cue> {
+        self.blocks.iter_mut().filter_map(|block| match block {
+            Block::Cue(cue) => Some(cue),
+            Block::Other(_) => None,
+        })
+    }
+
+    /// Translate the Chinese cue text. Numbering, timings and the styling tags
+    /// around each cue's text are left as they are. In bilingual mode the
+    /// translation is stacked under the original lines.
+    pub async fn translate(
+        &mut self,
+        service: &AITranslationService,
+        bilingual: bool,
+        batch_size: usize,
+        concurrency: usize,
+    ) -> Result<usize> {
+        let mut cues: Vec<(&mut Cue, Styled)> = self
+            .cues_mut()
+            .filter(|cue| cue.text.iter().any(|line| line.chars().any(is_han)))
+            .map(|cue| {
+                let styled = Styled::split(&cue.text);
+                (cue, styled)
+            })
+            .collect();
+        let items: Vec<String> = cues.iter().map(|(_, styled)| styled.text.clone()).collect();
+        let translations =
+            crate::chunk::translate_batches(service, &items, batch_size, concurrency).await?;
+
+        for ((cue, styled), translation) in cues.iter_mut().zip(translations) {
+            let translated = styled.wrap(&translation);
+            if bilingual {
+                cue.text.extend(translated);
+            } else {
+                cue.text = translated;
+            }
+        }
+        Ok(cues.len())
+    }
+}
+
+impl std::fmt::Display for Subtitles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.bom {
+            write!(f, "\u{feff}")?;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", self.newline)?;
+            }
+            let lines = match block {
+                Block::Cue(cue) => cue.header.iter().chain(&cue.text).collect::<Vec<_>>(),
+                Block::Other(lines) => lines.iter().collect(),
+            };
+            for line in lines {
+                write!(f, "{}{}", line, self.newline)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cue text with the tags that open its first line and close its last line taken
+/// off, e.g. `{\an8}<i>` and `</i>`, so they can be put back around the translation.
+#[derive(Debug, Default)]
+struct Styled {
+    prefix: String,
+    text: String,
+    suffix: String,
+}
+
+impl Styled {
+    fn split(lines: &[String]) -> Self {
+        let text = lines.join("\n");
+        let mut rest = text.as_str();
+        let mut prefix = String::new();
+        while let Some(tag) = leading_tag(rest) {
+            prefix.push_str(tag);
+            rest = &rest[tag.len()..];
+        }
+        let mut suffix = String::new();
+        while let Some(tag) = trailing_tag(rest) {
+            suffix.insert_str(0, tag);
+            rest = &rest[..rest.len() - tag.len()];
+        }
+        Self {
+            prefix,
+            text: rest.to_string(),
+            suffix,
+        }
+    }
+
+    fn wrap(&self, translation: &str) -> Vec<String> {
+        format!("{}{}{}", self.prefix, translation.trim(), self.suffix)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+fn leading_tag(text: &str) -> Option<&str> {
+    let close = match text.chars().next()? {
+        '<' => '>',
+        '{' => '}',
+        _ => return None,
+    };
+    text.find(close).map(|end| &text[..=end])
+}
+
+fn trailing_tag(text: &str) -> Option<&str> {
+    let open = match text.chars().last()? {
+        '>' => '<',
+        '}' => '{',
+        _ => return None,
+    };
+    text.rfind(open).map(|start| &text[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, chat_reply};
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>你好</i>\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\n♪ ♪\r\n\r\n3\r\n00:00:05,000 --> 00:00:07,000\r\n{\\an8}我们走吧\r\n快点\r\n";
+
+    const VTT: &str = "WEBVTT\n\nNOTE 这一段不翻译\n\nintro\n00:01.000 --> 00:02.000 align:start\n<v 小明>早上好\n";
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(parse(SRT).to_string(), SRT);
+        assert_eq!(parse(VTT).to_string(), VTT);
+
+        let styled = Styled::split(&["{\\an8}<i>我们走吧".to_string(), "快点</i>".to_string()]);
+        assert_eq!(styled.prefix, "{\\an8}<i>");
+        assert_eq!(styled.text, "我们走吧\n快点");
+        assert_eq!(styled.suffix, "</i>");
+    }
+
+    #[tokio::test]
+    async fn test_translate() {
+        let server = MockServer::start(vec![chat_reply(
+            r#"{"translations": ["Hello", "Let's go.\nHurry up."]}"#,
+        )])
+        .await;
+        let mut subtitles = parse(SRT);
+        let translated = subtitles
+            .translate(&server.service(), false, 20, 1)
+            .await
+            .unwrap();
+        assert_eq!(translated, 2);
+        assert_eq!(
+            subtitles.to_string(),
+            "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i>\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\n♪ ♪\r\n\r\n3\r\n00:00:05,000 --> 00:00:07,000\r\n{\\an8}Let's go.\r\nHurry up.\r\n"
+        );
+
+        let input: serde_json::Value = serde_json::from_str(
+            server.requests()[0]["messages"][1]["content"]
+                .as_str()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            input["items"],
+            serde_json::json!(["你好", "我们走吧\n快点"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_translate_bilingual() {
+        let server =
+            MockServer::start(vec![chat_reply(r#"{"translations": ["Good morning"]}"#)]).await;
+        let mut subtitles = parse(VTT);
+        subtitles
+            .translate(&server.service(), true, 20, 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            subtitles.to_string(),
+            "WEBVTT\n\nNOTE 这一段不翻译\n\nintro\n00:01.000 --> 00:02.000 align:start\n<v 小明>早上好\n<v 小明>Good morning\n"
+        );
+    }
+}
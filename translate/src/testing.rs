@@ -0,0 +1,120 @@
+//! Test helpers: fixture dictionaries and an in-process OpenAI-compatible chat server,
+//! so tests never touch `~/.translate` or a real API.
+
+use crate::ai::AITranslationService;
+use crate::dictionary::Dictionary;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+pub fn fixture_dict_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dict")
+}
+
+pub fn fixture_dictionary() -> Dictionary {
+    Dictionary::load(&fixture_dict_dir()).expect("fixture dictionaries should load")
+}
+
+/// A chat completion reply whose message is `content`. It reports no token usage,
+/// so the service doesn't write to the usage ledger in the data directory.
+pub fn chat_reply(content: &str) -> (u16, String) {
+    let body = json!({ "choices": [{ "message": { "role": "assistant", "content": content } }] });
+    (200, body.to_string())
+}
+
+/// A request the mock server received: its headers, with lowercase names, and JSON body.
+#[derive(Debug, Clone)]
+struct Request {
+    headers: HashMap<String, String>,
+    body: Value,
+}
+
+/// Serves queued `(status, body)` replies in order, one per request, and records
+/// the requests it received. Runs until the test's runtime shuts down.
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub async fn start(replies: Vec<(u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let replies = Arc::new(Mutex::new(VecDeque::from(replies)));
+
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufReader::new(stream);
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                    }
+                }
+                let content_length = headers
+                    .get("content-length")
+                    .and_then(|length| length.parse().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; content_length];
+                if stream.read_exact(&mut body).await.is_err() {
+                    continue;
+                }
+                recorded.lock().unwrap().push(Request {
+                    headers,
+                    body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+                });
+
+                let (status, reply) = replies
+                    .lock()
+                    .unwrap()
+                    .pop_front()
+                    .unwrap_or((500, "no reply queued".to_string()));
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                );
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+                let _ = stream.get_mut().shutdown().await;
+            }
+        });
+
+        Self { base_url, requests }
+    }
+
+    pub fn service(&self) -> AITranslationService {
+        AITranslationService::with_endpoint(&self.base_url, "test-key", "mock-model").unwrap()
+    }
+
+    /// Bodies of the requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|request| request.body.clone())
+            .collect()
+    }
+
+    /// Headers of the requests received so far, with lowercase names.
+    pub fn headers(&self) -> Vec<HashMap<String, String>> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|request| request.headers.clone())
+            .collect()
+    }
+}
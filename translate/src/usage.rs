@@ -0,0 +1,252 @@
+use crate::error::{Error, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Token counts reported by the API for a single request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// One line of the usage ledger. Only token counts are kept, never the text
+/// that was translated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    timestamp: DateTime<Local>,
+    model: String,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/// Price of a model per million tokens, in whatever currency the user chooses.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Price {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+impl Price {
+    fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+fn ledger_path() -> Result<PathBuf> {
+    Ok(crate::data_dir()?.join("usage.jsonl"))
+}
+
+fn prices_path() -> Result<PathBuf> {
+    Ok(crate::data_dir()?.join("prices.json"))
+}
+
+/// Append a request's token usage to the local ledger.
+pub fn record(model: &str, usage: &Usage) -> Result<()> {
+    append_record(&ledger_path()?, model, usage, Local::now())
+}
+
+fn append_record(
+    path: &Path,
+    model: &str,
+    usage: &Usage,
+    timestamp: DateTime<Local>,
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let record = Record {
+        timestamp,
+        model: model.to_string(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+fn load_records(path: &Path) -> Result<Vec<Record>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(path)?;
+    // Skip lines that fail to parse so a single corrupt write doesn't hide the rest
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Load the optional per-model price table, e.g. `{"glm-4.5": {"prompt": 0.8, "completion": 2.0}}`.
+fn load_prices(path: &Path) -> Result<HashMap<String, Price>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let data = fs::read_to_string(path)?;
+    serde_json::from_str(&data)
+        .map_err(|e| Error::Config(format!("Invalid {}: {}", path.display(), e)))
+}
+
+#[derive(Default)]
+struct Totals {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/// Build the day, week and month usage report.
+pub fn report() -> Result<String> {
+    let records = load_records(&ledger_path()?)?;
+    let prices = load_prices(&prices_path()?)?;
+    Ok(render_report(&records, &prices, Local::now().date_naive()))
+}
+
+fn render_report(records: &[Record], prices: &HashMap<String, Price>, today: NaiveDate) -> String {
+    if records.is_empty() {
+        return "No AI usage recorded yet.".to_string();
+    }
+
+    let week_start = today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64);
+    let month_start = today.with_day(1).unwrap_or(today);
+    let periods = [
+        ("Today", today),
+        ("This week", week_start),
+        ("This month", month_start),
+    ];
+
+    let mut lines = vec![format!(
+        "{:<12} {:<24} {:>8} {:>10} {:>10} {:>10}",
+        "Period", "Model", "Requests", "Prompt", "Completion", "Cost"
+    )];
+
+    for (label, since) in periods {
+        let mut by_model: BTreeMap<&str, Totals> = BTreeMap::new();
+        for record in records
+            .iter()
+            .filter(|r| r.timestamp.date_naive() >= since && r.timestamp.date_naive() <= today)
+        {
+            let totals = by_model.entry(&record.model).or_default();
+            totals.requests += 1;
+            totals.prompt_tokens += record.prompt_tokens;
+            totals.completion_tokens += record.completion_tokens;
+        }
+
+        if by_model.is_empty() {
+            lines.push(format!("{:<12} {:<24} {:>8}", label, "-", 0));
+            continue;
+        }
+
+        let mut total = Totals::default();
+        let mut total_cost = Some(0.0);
+        for (model, totals) in &by_model {
+            let cost = prices
+                .get(*model)
+                .map(|price| price.cost(totals.prompt_tokens, totals.completion_tokens));
+            total.requests += totals.requests;
+            total.prompt_tokens += totals.prompt_tokens;
+            total.completion_tokens += totals.completion_tokens;
+            total_cost = total_cost.zip(cost).map(|(a, b)| a + b);
+            lines.push(format_row(label, model, totals, cost));
+        }
+        if by_model.len() > 1 {
+            lines.push(format_row(label, "(total)", &total, total_cost));
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn format_row(label: &str, model: &str, totals: &Totals, cost: Option<f64>) -> String {
+    format!(
+        "{:<12} {:<24} {:>8} {:>10} {:>10} {:>10}",
+        label,
+        model,
+        totals.requests,
+        totals.prompt_tokens,
+        totals.completion_tokens,
+        cost.map(|c| format!("{:.4}", c))
+            .unwrap_or_else(|| "-".to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record_at(day: u32, model: &str, prompt: u64, completion: u64) -> Record {
+        Record {
+            timestamp: Local.with_ymd_and_hms(2025, 9, day, 12, 0, 0).unwrap(),
+            model: model.to_string(),
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+        }
+    }
+
+    #[test]
+    fn test_ledger_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("translate-usage-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let usage = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 20,
+        };
+        append_record(&path, "glm-4.5", &usage, Local::now()).unwrap();
+        append_record(&path, "deepseek-chat", &usage, Local::now()).unwrap();
+
+        let records = load_records(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].model, "deepseek-chat");
+        assert_eq!(records[1].completion_tokens, 20);
+    }
+
+    #[test]
+    fn test_render_report() {
+        // 2025-09-17 is a Wednesday, so the week starts on the 15th
+        let today = NaiveDate::from_ymd_opt(2025, 9, 17).unwrap();
+        let records = vec![
+            record_at(17, "glm-4.5", 1000, 2000),
+            record_at(16, "glm-4.5", 1000, 0),
+            record_at(2, "deepseek-chat", 500, 500),
+        ];
+        let mut prices = HashMap::new();
+        prices.insert(
+            "glm-4.5".to_string(),
+            Price {
+                prompt: 1.0,
+                completion: 2.0,
+            },
+        );
+
+        let report = render_report(&records, &prices, today);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[1].starts_with("Today") && lines[1].contains("0.0050"));
+        assert!(lines[2].starts_with("This week") && lines[2].contains("2000"));
+        assert!(lines[2].contains("0.0060"));
+        // The month mixes a priced and an unpriced model, so the total cost is unknown
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.starts_with("This month") && l.contains("deepseek-chat"))
+        );
+        assert!(lines.last().unwrap().contains("(total)") && lines.last().unwrap().ends_with('-'));
+    }
+}
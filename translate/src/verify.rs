@@ -0,0 +1,95 @@
+use crate::chinese::is_cjk_punctuation;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Default similarity below which a back-translation is considered to have drifted.
+pub const DEFAULT_THRESHOLD: f32 = 0.5;
+
+/// The outcome of translating an AI result back to Chinese.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Verification {
+    /// The English translation rendered back into Chinese
+    pub back_translation: String,
+    /// Similarity between the original and the back-translation, between 0.0 and 1.0
+    pub similarity: f32,
+    /// The threshold the similarity was checked against
+    pub threshold: f32,
+}
+
+impl Verification {
+    pub fn new(original: &str, back_translation: &str, threshold: f32) -> Self {
+        Self {
+            back_translation: back_translation.to_string(),
+            similarity: similarity(original, back_translation),
+            threshold,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.similarity >= self.threshold
+    }
+}
+
+/// Dice coefficient over character bigrams, ignoring whitespace and punctuation.
+/// Texts too short to have a bigram are compared character by character instead.
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = significant_chars(a);
+    let b: Vec<char> = significant_chars(b);
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let n = if a.len() < 2 || b.len() < 2 { 1 } else { 2 };
+    let grams_a = ngram_counts(&a, n);
+    let grams_b = ngram_counts(&b, n);
+    let total: usize = grams_a.values().sum::<usize>() + grams_b.values().sum::<usize>();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let shared: usize = grams_a
+        .iter()
+        .map(|(gram, count)| (*count).min(grams_b.get(gram).copied().unwrap_or(0)))
+        .sum();
+    2.0 * shared as f32 / total as f32
+}
+
+fn significant_chars(text: &str) -> Vec<char> {
+    text.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_ascii_punctuation() && !is_cjk_punctuation(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn ngram_counts(chars: &[char], n: usize) -> HashMap<&[char], usize> {
+    let mut counts = HashMap::new();
+    for gram in chars.windows(n) {
+        *counts.entry(gram).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("制造或修理钟表者", "制造或修理钟表者"), 1.0);
+        assert_eq!(similarity("你好。", "你好"), 1.0);
+        assert_eq!(similarity("钟表", "电脑"), 0.0);
+        // 修理钟表 shares three of its bigrams with 修理钟表的人
+        let score = similarity("修理钟表", "修理钟表的人");
+        assert!((score - 0.75).abs() < 1e-6);
+        // Single characters fall back to unigrams
+        assert_eq!(similarity("钟", "钟"), 1.0);
+    }
+
+    #[test]
+    fn test_verification_threshold() {
+        let verification = Verification::new("修理钟表", "维修手表", DEFAULT_THRESHOLD);
+        assert!(!verification.passed());
+        let verification = Verification::new("修理钟表", "修理钟表", DEFAULT_THRESHOLD);
+        assert!(verification.passed());
+    }
+}